@@ -14,394 +14,104 @@ fn main() {
 }
 
 fn setup(mut commands: Commands, ass: Res<AssetServer>) {
-    commands.spawn_bundle(TerrainBundle {
+    let mut terrain = commands.spawn_bundle(TerrainBundle {
         terrain: Terrain::new(
             GenerationType::WaveCollapse,
             UVec2::splat(50),
             Vec2::splat(16.0),
         )
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [2, 3, 66, 86].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [22, 43, 66, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [21, 42, 43, 44, 45].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [3, 21, 24, 42, 45].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 1,
-            image: ass.load("2d/tile001.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [2, 3, 66, 86].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [4, 23, 25, 44, 46, 64, 65].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [21, 42, 43, 44, 45].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [1, 2, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 2,
-            image: ass.load("2d/tile002.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [1, 21, 22, 42, 43].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [24, 45, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [21, 42, 43, 44, 45].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [1, 2, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 3,
-            image: ass.load("2d/tile003.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [4, 23, 24, 25, 46, 64, 85].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [4, 23, 25, 44, 46, 64, 65].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [2, 4, 23, 25, 46, 85, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [4, 22, 23, 25, 46, 65, 86].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 4,
-            image: ass.load("2d/tile004.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [1, 21, 22, 42, 43].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [1, 2, 3, 21, 42].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [21, 42, 43, 44, 45].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [1, 21, 24, 42, 45].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 21,
-            image: ass.load("2d/tile021.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [4, 23, 24, 25, 46, 64, 85].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [22, 43, 66, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [1, 22, 65, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [3, 21, 24, 42, 45].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 22,
-            image: ass.load("2d/tile022.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [4, 23, 24, 25, 46].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [4, 23, 25, 44, 46].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [2, 4, 23, 25, 2, 46].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [4, 22, 23, 25, 46].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 23,
-            image: ass.load("2d/tile023.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [1, 21, 22, 42, 43].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [24, 45, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [1, 22, 65, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [4, 22, 23, 25, 46, 65, 86].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 24,
-            image: ass.load("2d/tile024.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [44, 45, 65, 87].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [1, 2, 3, 21, 42].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [1, 22, 65, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [3, 21, 24, 42, 45].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 43,
-            image: ass.load("2d/tile043.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [44, 45, 65, 87].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [1, 2, 3, 21, 42].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [2, 4, 23, 25, 46, 85, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [43, 44, 64, 66].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 44,
-            image: ass.load("2d/tile044.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [1, 21, 22, 42, 43].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [1, 2, 3, 21, 42].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [1, 22, 65, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [43, 44, 64, 66].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 45,
-            image: ass.load("2d/tile045.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [44, 45, 65, 87].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [24, 45, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [2, 4, 85, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [4, 22, 65, 86].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 64,
-            image: ass.load("2d/tile064.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [4, 24, 64, 85].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [22, 43, 66, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [2, 4, 85, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [43, 44, 64, 66].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 65,
-            image: ass.load("2d/tile065.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [44, 45, 65, 87].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [24, 45, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [1, 22, 65, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [1, 2, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 66,
-            image: ass.load("2d/tile066.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [2, 3, 66, 86].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [4, 44, 64, 65].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [3, 24, 64, 66].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [4, 22, 65, 86].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 85,
-            image: ass.load("2d/tile085.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [4, 24, 64, 85].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [4, 44, 64, 65].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [1, 22, 65, 87].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [1, 2, 85, 87].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 86,
-            image: ass.load("2d/tile086.png"),
-        })
-        .with_module(TerrainModule {
-            generation_rule: |adjacents| {
-                adjacents
-                    .e
-                    .map(|module| [2, 3, 66, 86].contains(&module.id))
-                    .unwrap_or(true)
-                    && adjacents
-                        .s
-                        .map(|module| [22, 43, 66, 86].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .n
-                        .map(|module| [3, 24, 64, 66].contains(&module.id))
-                        .unwrap_or(true)
-                    && adjacents
-                        .w
-                        .map(|module| [43, 44, 64, 66].contains(&module.id))
-                        .unwrap_or(true)
-            },
-            id: 87,
-            image: ass.load("2d/tile087.png"),
-        }),
+        .with_module(tile(
+            &ass,
+            "tile001",
+            [&["v4"], &["h1"], &["v1"], &["h2", "h4"]],
+        ))
+        .with_module(tile(&ass, "tile002", [&["v4"], &["h1"], &["v2"], &["h1"]]))
+        .with_module(tile(&ass, "tile003", [&["v4"], &["h2"], &["v3"], &["h1"]]))
+        .with_module(tile(
+            &ass,
+            "tile004",
+            [&["v2", "v5", "v6"], &["h3"], &["v2"], &["h3", "h5", "h7"]],
+        ))
+        .with_module(tile(&ass, "tile021", [&["v4"], &["h4"], &["v4"], &["h4"]]))
+        .with_module(tile(
+            &ass,
+            "tile022",
+            [&["v1"], &["h3"], &["v1"], &["h2", "h4"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile023",
+            [&["v2", "v5"], &["h5"], &["v5"], &["h3", "h5"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile024",
+            [&["edge"], &["h4"], &["v3"], &["h3", "h5", "h7"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile043",
+            [&["v1"], &["h6"], &["v4"], &["h2", "h4"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile044",
+            [&["v2", "v5", "v6"], &["h6"], &["v4"], &["h6"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile045",
+            [&["edge"], &["h4"], &["v4"], &["h6"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile064",
+            [&["v2", "v6"], &["h6"], &["v3"], &["h3", "h7"]],
+        ))
+        .with_module(tile(
+            &ass,
+            "tile065",
+            [&["v2", "v6"], &["h7"], &["v1"], &["h6"]],
+        ))
+        .with_module(tile(&ass, "tile066", [&["v1"], &["h6"], &["v3"], &["h1"]]))
+        .with_module(tile(
+            &ass,
+            "tile085",
+            [&["v3"], &["h1"], &["v6"], &["h3", "h7"]],
+        ))
+        .with_module(tile(&ass, "tile086", [&["v1"], &["h7"], &["v6"], &["h1"]]))
+        .with_module(tile(&ass, "tile087", [&["v3"], &["h1"], &["v1"], &["h6"]])),
         transform: Transform::from_xyz(-25.0 * 16.0 * 4.0, 25.0 * 16.0 * 4.0, 0.0)
             .with_scale(Vec2::splat(4.0).extend(0.0)),
         ..default()
     });
+    // With TERRAIN_DEBUG set, draws generation as it happens, P pauses it and N advances it by a
+    // single step
+    if std::env::var_os("TERRAIN_DEBUG").is_some() {
+        terrain.insert(TerrainDebug::default());
+    }
 
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
 }
 
+/// Grass and dirt tile from `assets/2d`. The tags on each side, north, east, south and west, name
+/// the kinds of edges the tile has there, two tiles fit next to each other when the sides facing
+/// each other share one. `edge` is on no other side, so those tiles only go at the border.
+fn tile(ass: &AssetServer, name: &str, [n, e, s, w]: [&[&str]; 4]) -> TerrainModule {
+    let tags = |side: &[&str]| -> Vec<String> { side.iter().map(|tag| tag.to_string()).collect() };
+    TerrainModule {
+        name: name.to_string(),
+        image: ass.load(format!("2d/{}.png", name).as_str()),
+        sockets: Sockets {
+            n: tags(n),
+            e: tags(e),
+            s: tags(s),
+            w: tags(w),
+        },
+        ..default()
+    }
+}
+
 fn camera(
     keys: Res<Input<KeyCode>>,
     time: Res<Time>,
@@ -0,0 +1,314 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+
+use crate::{
+    heightmap::{build, sample},
+    NoiseSource, Terrain, TerrainModuleId, URect,
+};
+
+/// Settings for [`GenerationType::Autotile`](crate::GenerationType::Autotile).
+///
+/// Every cell gets a terrain type first, then the module of each cell is looked up by which of
+/// its neighbours have a type at least as high as its own. Types are ordered from low to high,
+/// so the transition between two types is made by the tiles of the higher one.
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct AutotileSettings {
+    pub mode: AutotileMode,
+    pub source: TypeSource,
+    pub types: Vec<TerrainType>,
+}
+
+impl AutotileSettings {
+    pub const N: u8 = 1;
+    pub const NE: u8 = 2;
+    pub const E: u8 = 4;
+    pub const SE: u8 = 8;
+    pub const S: u8 = 16;
+    pub const SW: u8 = 32;
+    pub const W: u8 = 64;
+    pub const NW: u8 = 128;
+
+    pub fn new(mode: AutotileMode, source: TypeSource) -> Self {
+        Self {
+            mode,
+            source,
+            types: vec![],
+        }
+    }
+
+    /// Adds a type above the ones added before it
+    pub fn with_type(mut self, terrain_type: TerrainType) -> Self {
+        self.types.push(terrain_type);
+        self
+    }
+}
+
+/// Which neighbours make up the mask of a cell, using the bits of [`AutotileSettings`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Inspectable)]
+pub enum AutotileMode {
+    /// `N`, `E`, `S` and `W`, for 16 tile sets
+    Cardinal,
+    /// All eight neighbours, corners only count when both sides next to them do, for 47 tile
+    /// blob sets
+    Blob,
+    /// `NE`, `SE`, `SW` and `NW`, a corner counts when all three cells around it do, for 16 tile
+    /// corner Wang sets
+    Corners,
+}
+
+impl Default for AutotileMode {
+    fn default() -> Self {
+        AutotileMode::Cardinal
+    }
+}
+
+/// Where the terrain type of every cell comes from
+#[derive(Clone, Debug, Inspectable)]
+pub enum TypeSource {
+    /// The type whose `below` is the first one higher than the noise value at the cell
+    Noise { sources: Vec<NoiseSource> },
+    /// Type indices row by row, cells past the end get type 0
+    Grid { types: Vec<usize> },
+    /// Cells placed on the layer before it is generated keep their module, which sets their type,
+    /// other cells take the type of the closest one
+    Pinned,
+}
+
+impl Default for TypeSource {
+    fn default() -> Self {
+        TypeSource::Pinned
+    }
+}
+
+/// One type of terrain and its transition tiles
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct TerrainType {
+    pub name: String,
+    /// Module for cells surrounded by this type or higher ones
+    pub fill: TerrainModuleId,
+    /// Modules by mask, masks without a tile use `fill`
+    pub tiles: Vec<AutotileTile>,
+    /// For [`TypeSource::Noise`], the highest noise value of the type
+    pub below: f64,
+}
+
+impl TerrainType {
+    pub fn new(name: impl Into<String>, fill: TerrainModuleId) -> Self {
+        Self {
+            name: name.into(),
+            fill,
+            ..default()
+        }
+    }
+
+    pub fn below(mut self, below: f64) -> Self {
+        self.below = below;
+        self
+    }
+
+    pub fn with_tile(mut self, mask: u8, id: TerrainModuleId) -> Self {
+        self.tiles.push(AutotileTile { mask, id });
+        self
+    }
+
+    fn module(&self, mask: u8) -> TerrainModuleId {
+        self.tiles
+            .iter()
+            .find(|tile| tile.mask == mask)
+            .map_or(self.fill, |tile| tile.id)
+    }
+
+    fn has_module(&self, id: TerrainModuleId) -> bool {
+        self.fill == id || self.tiles.iter().any(|tile| tile.id == id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Inspectable)]
+pub struct AutotileTile {
+    pub mask: u8,
+    pub id: TerrainModuleId,
+}
+
+impl Terrain {
+    /// Fills every empty cell of `layer` with the tile for its type and its neighbours' types.
+    /// Tiles already placed next to empty cells are picked again, so their edges match the new
+    /// cells.
+    pub(crate) fn generate_autotile(&mut self, layer: usize, settings: &AutotileSettings) {
+        if settings.types.is_empty() {
+            warn!(
+                "No terrain types added to layer {}!",
+                self.layers[layer].name
+            );
+            return;
+        }
+        let types = self.terrain_types(layer, settings);
+        let type_at = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= self.dimensions.x as i64 || y >= self.dimensions.y as i64 {
+                return None;
+            }
+            Some(types[(y as u32 * self.dimensions.x + x as u32) as usize])
+        };
+
+        let map = &self.layers[layer].map;
+        let is_empty = |x: i64, y: i64| {
+            x >= 0
+                && y >= 0
+                && x < self.dimensions.x as i64
+                && y < self.dimensions.y as i64
+                && !map.contains_key(&UVec2::new(x as u32, y as u32))
+        };
+
+        let mut placed = vec![];
+        for pos in URect::new(UVec2::ZERO, self.dimensions).positions() {
+            let (x, y) = (pos.x as i64, pos.y as i64);
+            let old = map.get(&pos).map(|module| module.id);
+            if let Some(old) = old {
+                let on_border = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                    .any(|(dx, dy)| is_empty(x + dx, y + dy));
+                let is_tile = settings.types.iter().any(|kind| kind.has_module(old));
+                if !on_border || !is_tile {
+                    continue;
+                }
+            }
+            // Unwrap is fine because `pos` is in bounds
+            let own = type_at(x, y).unwrap();
+            // Out of bounds continues the cell's own type
+            let at_least = |dx, dy| type_at(x + dx, y + dy).map_or(true, |other| other >= own);
+            let n = at_least(0, -1);
+            let e = at_least(1, 0);
+            let s = at_least(0, 1);
+            let w = at_least(-1, 0);
+            let ne = at_least(1, -1);
+            let se = at_least(1, 1);
+            let sw = at_least(-1, 1);
+            let nw = at_least(-1, -1);
+            type A = AutotileSettings;
+            let bits = match settings.mode {
+                AutotileMode::Cardinal => vec![(n, A::N), (e, A::E), (s, A::S), (w, A::W)],
+                AutotileMode::Blob => vec![
+                    (n, A::N),
+                    (n && e && ne, A::NE),
+                    (e, A::E),
+                    (s && e && se, A::SE),
+                    (s, A::S),
+                    (s && w && sw, A::SW),
+                    (w, A::W),
+                    (n && w && nw, A::NW),
+                ],
+                AutotileMode::Corners => vec![
+                    (n && e && ne, A::NE),
+                    (s && e && se, A::SE),
+                    (s && w && sw, A::SW),
+                    (n && w && nw, A::NW),
+                ],
+            };
+            let mask = bits
+                .iter()
+                .filter(|(set, _)| *set)
+                .fold(0, |mask, (_, bit)| mask | bit);
+            let full = bits.iter().fold(0, |mask, (_, bit)| mask | bit);
+            let terrain_type = &settings.types[own];
+            let id = if mask == full {
+                terrain_type.fill
+            } else {
+                terrain_type.module(mask)
+            };
+            if old != Some(id) {
+                placed.push((pos, id, old.is_some()));
+            }
+        }
+        for (pos, id, replaced) in placed {
+            if replaced {
+                self.clear(layer, pos);
+            }
+            self.place(layer, pos, id);
+        }
+    }
+
+    /// Index into `settings.types` of every cell, row by row
+    fn terrain_types(&self, layer: usize, settings: &AutotileSettings) -> Vec<usize> {
+        let cells = URect::new(UVec2::ZERO, self.dimensions).positions();
+        let last = settings.types.len() - 1;
+        match &settings.source {
+            TypeSource::Noise { sources } => {
+                let noise = build(sources, self.seed);
+                cells
+                    .map(|pos| {
+                        let value = sample(sources, &noise, pos);
+                        settings
+                            .types
+                            .iter()
+                            .position(|terrain_type| value < terrain_type.below)
+                            .unwrap_or(last)
+                    })
+                    .collect()
+            }
+            TypeSource::Grid { types } => (0..(self.dimensions.x * self.dimensions.y) as usize)
+                .map(|index| types.get(index).copied().unwrap_or(0).min(last))
+                .collect(),
+            TypeSource::Pinned => {
+                // Grid order, so ties between pinned cells break the same way every time
+                let map = &self.layers[layer].map;
+                let pinned: Vec<(UVec2, usize)> = URect::new(UVec2::ZERO, self.dimensions)
+                    .positions()
+                    .filter_map(|pos| {
+                        let module = map.get(&pos)?;
+                        let index = settings
+                            .types
+                            .iter()
+                            .position(|terrain_type| terrain_type.has_module(module.id))?;
+                        Some((pos, index))
+                    })
+                    .collect();
+                cells
+                    .map(|pos| {
+                        pinned
+                            .iter()
+                            .min_by_key(|(pinned, _)| {
+                                let distance = pinned.as_ivec2() - pos.as_ivec2();
+                                distance.x * distance.x + distance.y * distance.y
+                            })
+                            .map_or(0, |(_, index)| *index)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, name_at},
+        GenerationType,
+    };
+
+    #[test]
+    fn picks_tiles_by_neighbour_mask() {
+        let mut terrain = testing::terrain(
+            GenerationType::Autotile(default()),
+            UVec2::new(3, 2),
+            &["water", "land", "shore"],
+        );
+        let id = |name| testing::id(&terrain, name);
+        type A = AutotileSettings;
+        let settings = AutotileSettings::new(
+            AutotileMode::Cardinal,
+            TypeSource::Grid {
+                types: vec![0, 1, 1, 0, 1, 1],
+            },
+        )
+        .with_type(TerrainType::new("water", id("water")))
+        .with_type(TerrainType::new("land", id("land")).with_tile(A::N | A::E | A::S, id("shore")));
+        terrain.generate_autotile(0, &settings);
+        for y in 0..2 {
+            // Water is the lowest type, so it is surrounded by types at least as high
+            assert_eq!(name_at(&terrain, UVec2::new(0, y)), Some("water"));
+            // Only the west neighbour is lower, past the edge counts as the cell's own type
+            assert_eq!(name_at(&terrain, UVec2::new(1, y)), Some("shore"));
+            assert_eq!(name_at(&terrain, UVec2::new(2, y)), Some("land"));
+        }
+    }
+}
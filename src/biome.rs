@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+
+use crate::{
+    get_adjacent_positions,
+    heightmap::{build, sample},
+    NoiseSource, Terrain, TerrainModule, URect,
+};
+
+/// Settings for [`GenerationType::Biomes`](crate::GenerationType::Biomes).
+///
+/// Every cell is given a biome first, then wave collapse only considers the modules tagged with
+/// the biome of the cell in [`TerrainModule::biomes`]. Cells on the border between biomes also
+/// allow the modules of the neighbouring biomes, so transition modules tagged with both fit there.
+#[derive(Clone, Debug, Inspectable)]
+pub enum BiomeSettings {
+    /// Picks biomes from bands of low frequency noise
+    Noise {
+        sources: Vec<NoiseSource>,
+        /// Ordered from low to high values, the last band also takes any value above it
+        bands: Vec<BiomeBand>,
+    },
+    /// Scatters `points` randomly, each with a random biome, cells take the biome of the closest
+    Voronoi { points: usize, biomes: Vec<String> },
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        BiomeSettings::Voronoi {
+            points: 8,
+            biomes: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct BiomeBand {
+    pub below: f64,
+    pub biome: String,
+}
+
+impl BiomeBand {
+    pub fn new(below: f64, biome: impl Into<String>) -> Self {
+        Self {
+            below,
+            biome: biome.into(),
+        }
+    }
+}
+
+impl TerrainModule {
+    /// Modules without biomes are allowed in any biome
+    pub fn fits_biomes(&self, biomes: &[&str]) -> bool {
+        self.biomes.is_empty()
+            || self
+                .biomes
+                .iter()
+                .any(|biome| biomes.contains(&biome.as_str()))
+    }
+}
+
+impl Terrain {
+    /// Assigns a biome to every cell of `layer`
+    pub(crate) fn generate_biomes(&mut self, layer: usize, settings: &BiomeSettings) {
+        let cells = URect::new(UVec2::ZERO, self.dimensions).positions();
+        let biomes = match settings {
+            BiomeSettings::Noise { sources, bands } => {
+                if bands.is_empty() {
+                    warn!(
+                        "No biome bands added to layer {}, generating it without biomes!",
+                        self.layers[layer].name
+                    );
+                    return;
+                }
+                let noise = build(sources, self.seed);
+                cells
+                    .map(|pos| {
+                        let value = sample(sources, &noise, pos);
+                        // Unwrap is fine because we already checked if the vec is empty
+                        let band = bands
+                            .iter()
+                            .find(|band| value < band.below)
+                            .unwrap_or_else(|| bands.last().unwrap());
+                        (pos, band.biome.clone())
+                    })
+                    .collect()
+            }
+            BiomeSettings::Voronoi { points, biomes } => {
+                if biomes.is_empty() || *points == 0 {
+                    warn!(
+                        "No biomes added to layer {}, generating it without biomes!",
+                        self.layers[layer].name
+                    );
+                    return;
+                }
+                let points: Vec<(Vec2, &String)> = (0..*points)
+                    .map(|_| {
+                        let x = self.rng.gen_range(0..self.dimensions.x);
+                        let y = self.rng.gen_range(0..self.dimensions.y);
+                        let biome = &biomes[self.rng.gen_range(0..biomes.len())];
+                        (UVec2::new(x, y).as_vec2(), biome)
+                    })
+                    .collect();
+                cells
+                    .map(|pos| {
+                        // Unwrap is fine because we already checked there are points
+                        let (_, biome) = points
+                            .iter()
+                            .min_by(|(a, _), (b, _)| {
+                                let a = a.distance_squared(pos.as_vec2());
+                                let b = b.distance_squared(pos.as_vec2());
+                                a.total_cmp(&b)
+                            })
+                            .unwrap();
+                        (pos, (*biome).clone())
+                    })
+                    .collect()
+            }
+        };
+        self.layers[layer].biomes = biomes;
+    }
+
+    /// Biome of `pos` on `layer` along with the biomes bordering it
+    pub(crate) fn biomes_around(&self, layer: usize, pos: UVec2) -> Vec<&str> {
+        let biomes = &self.layers[layer].biomes;
+        let mut around = vec![];
+        for pos in std::iter::once(pos).chain(get_adjacent_positions(&pos)) {
+            if let Some(biome) = biomes.get(&pos) {
+                if !around.contains(&biome.as_str()) {
+                    around.push(biome.as_str());
+                }
+            }
+        }
+        around
+    }
+}
@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+
+use crate::{
+    get_adjacent_positions,
+    heightmap::{build, sample},
+    nav::a_star,
+    NoiseSource, Terrain,
+};
+
+/// How a [`PathConstraint`](crate::PathConstraint) is carved between its ends.
+///
+/// The carved cells are reserved for the path's modules, then wave collapse picks which of them
+/// go where, so straight, corner and junction pieces line up with the route.
+#[derive(Clone, Debug, Inspectable)]
+pub enum Route {
+    /// As short as possible, randomly zigzagging between the ends
+    Shortest,
+    /// Random walk that steps towards the far end with a chance of `straightness`, and in any
+    /// direction otherwise. Loops are cut out, so cells aren't visited twice.
+    Wander { straightness: f32 },
+    /// Cheapest route when entering a cell costs 1 plus `cost` times the noise at it, so the route
+    /// follows valleys of the noise
+    Noise { source: NoiseSource, cost: f32 },
+    /// Smooth curve through `points` control points spread between the ends, each pushed up to
+    /// `jitter` cells to the side
+    Spline { points: usize, jitter: u32 },
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Route::Shortest
+    }
+}
+
+impl Terrain {
+    /// Cells from `from` to `to`, each next to the one before it
+    pub(crate) fn carve(&mut self, from: UVec2, to: UVec2, route: &Route) -> Vec<UVec2> {
+        match route {
+            Route::Shortest => self.shortest(from, to),
+            Route::Wander { straightness } => self.wander(from, to, *straightness),
+            Route::Noise { source, cost } => self.cheapest(from, to, source, *cost),
+            Route::Spline { points, jitter } => self.spline(from, to, *points, *jitter),
+        }
+    }
+
+    /// Random walk that only ever steps closer to `to`, so it is as short as a walk can be
+    fn shortest(&mut self, from: UVec2, to: UVec2) -> Vec<UVec2> {
+        let mut pos = from;
+        let mut route = vec![pos];
+        while pos != to {
+            let dx = to.x as i64 - pos.x as i64;
+            let dy = to.y as i64 - pos.y as i64;
+            let step_x = self.rng.gen_range(0..dx.abs() + dy.abs()) < dx.abs();
+            if step_x {
+                pos.x = (pos.x as i64 + dx.signum()) as u32;
+            } else {
+                pos.y = (pos.y as i64 + dy.signum()) as u32;
+            }
+            route.push(pos);
+        }
+        route
+    }
+
+    fn wander(&mut self, from: UVec2, to: UVec2, straightness: f32) -> Vec<UVec2> {
+        // Gives up wandering eventually and heads straight for the end
+        let max_steps = (self.dimensions.x * self.dimensions.y * 4) as usize;
+        let mut route = vec![from];
+        let mut pos = from;
+        for _ in 0..max_steps {
+            if pos == to {
+                return route;
+            }
+            let next = if self.rng.gen::<f32>() < straightness {
+                self.shortest(pos, to)[1]
+            } else {
+                let options: Vec<UVec2> = get_adjacent_positions(&pos)
+                    .into_iter()
+                    .filter(|next| next.x < self.dimensions.x && next.y < self.dimensions.y)
+                    .collect();
+                options[self.rng.gen_range(0..options.len())]
+            };
+            step(&mut route, next);
+            pos = next;
+        }
+        for next in self.shortest(pos, to) {
+            step(&mut route, next);
+        }
+        route
+    }
+
+    /// A* over the cost of the noise
+    fn cheapest(&mut self, from: UVec2, to: UVec2, source: &NoiseSource, cost: f32) -> Vec<UVec2> {
+        let sources = [*source];
+        let noise = build(&sources, self.seed);
+        let dimensions = self.dimensions;
+        let step_cost = |pos: UVec2| {
+            (pos.x < dimensions.x && pos.y < dimensions.y)
+                .then(|| 1.0 + cost.max(0.0) * sample(&sources, &noise, pos) as f32)
+        };
+        match a_star(from, to, 1.0, step_cost) {
+            Some(route) => route,
+            None => self.shortest(from, to),
+        }
+    }
+
+    fn spline(&mut self, from: UVec2, to: UVec2, points: usize, jitter: u32) -> Vec<UVec2> {
+        let start = from.as_vec2();
+        let end = to.as_vec2();
+        let side = (end - start).perp().normalize_or_zero();
+        let max = (self.dimensions - UVec2::ONE).as_vec2();
+        let mut controls = vec![start];
+        for point in 1..=points {
+            let along = start.lerp(end, point as f32 / (points + 1) as f32);
+            let push = self.rng.gen_range(-(jitter as f32)..=jitter as f32);
+            controls.push((along + side * push).clamp(Vec2::ZERO, max));
+        }
+        controls.push(end);
+
+        let mut route = vec![from];
+        for index in 0..controls.len() - 1 {
+            // Catmull-Rom through the controls, the ends are repeated to get a tangent there
+            let p0 = controls[index.saturating_sub(1)];
+            let p1 = controls[index];
+            let p2 = controls[index + 1];
+            let p3 = controls[(index + 2).min(controls.len() - 1)];
+            let samples = (p1.distance(p2) * 2.0).ceil().max(1.0) as usize;
+            for sample in 1..=samples {
+                let t = sample as f32 / samples as f32;
+                let point = 0.5
+                    * (2.0 * p1
+                        + (p2 - p0) * t
+                        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+                        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t);
+                let cell = point.round().clamp(Vec2::ZERO, max).as_uvec2();
+                // Fills the gap to the last cell, so the route stays connected. Unwrap is fine
+                // because the route starts out with `from`.
+                let last = *route.last().unwrap();
+                for next in self.shortest(last, cell) {
+                    step(&mut route, next);
+                }
+            }
+        }
+        route
+    }
+}
+
+/// Adds `next` to `route`, cutting out the loop if the route has been there before
+fn step(route: &mut Vec<UVec2>, next: UVec2) {
+    match route.iter().position(|visited| *visited == next) {
+        Some(index) => route.truncate(index + 1),
+        None => route.push(next),
+    }
+}
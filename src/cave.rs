@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+
+use crate::{Terrain, TerrainModuleId, URect};
+
+/// Settings for [`GenerationType::CellularAutomata`](crate::GenerationType::CellularAutomata).
+///
+/// Cells start out as walls with a chance of `fill`, then each smoothing iteration turns floors
+/// with at least `birth` walls around them into walls, and keeps walls with at least `survival`
+/// walls around them. Out of bounds counts as wall, so caves are always closed off.
+#[derive(Clone, Debug, Inspectable)]
+pub struct CaveSettings {
+    pub fill: f32,
+    pub iterations: usize,
+    pub birth: usize,
+    pub survival: usize,
+    /// Floor regions with fewer cells are filled with walls
+    pub min_floor_region: usize,
+    /// Wall regions with fewer cells are turned into floor
+    pub min_wall_region: usize,
+    pub floor: TerrainModuleId,
+    pub wall: TerrainModuleId,
+    /// Modules for walls next to floor, picked by which sides the floor is on
+    pub edges: Vec<CaveEdge>,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            fill: 0.45,
+            iterations: 5,
+            birth: 5,
+            survival: 4,
+            min_floor_region: 20,
+            min_wall_region: 10,
+            floor: default(),
+            wall: default(),
+            edges: vec![],
+        }
+    }
+}
+
+impl CaveSettings {
+    pub fn new(floor: TerrainModuleId, wall: TerrainModuleId) -> Self {
+        Self {
+            floor,
+            wall,
+            ..default()
+        }
+    }
+
+    /// Uses the module with `id` for walls with floor on exactly the `sides` given
+    pub fn with_edge(mut self, sides: u8, id: TerrainModuleId) -> Self {
+        self.edges.push(CaveEdge { sides, id });
+        self
+    }
+}
+
+/// Wall module for a combination of sides with floor next to them
+#[derive(Clone, Copy, Debug, Default, Inspectable)]
+pub struct CaveEdge {
+    /// Any of [`CaveEdge::N`], [`CaveEdge::E`], [`CaveEdge::S`] and [`CaveEdge::W`] combined
+    pub sides: u8,
+    pub id: TerrainModuleId,
+}
+
+impl CaveEdge {
+    pub const N: u8 = 1;
+    pub const E: u8 = 2;
+    pub const S: u8 = 4;
+    pub const W: u8 = 8;
+}
+
+/// Which cells of a cave are walls, row by row
+struct Cave {
+    dimensions: UVec2,
+    walls: Vec<bool>,
+    /// Cells that were already placed, which smoothing leaves alone
+    pinned: Vec<bool>,
+}
+
+impl Cave {
+    fn is_wall(&self, x: i64, y: i64) -> bool {
+        if x < 0 || y < 0 || x >= self.dimensions.x as i64 || y >= self.dimensions.y as i64 {
+            return true;
+        }
+        self.walls[(y as u32 * self.dimensions.x + x as u32) as usize]
+    }
+
+    fn walls_around(&self, pos: UVec2) -> usize {
+        let (x, y) = (pos.x as i64, pos.y as i64);
+        let mut walls = 0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) != (0, 0) && self.is_wall(x + dx, y + dy) {
+                    walls += 1;
+                }
+            }
+        }
+        walls
+    }
+
+    fn smooth(&mut self, birth: usize, survival: usize) {
+        self.walls = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .zip(self.pinned.iter())
+            .map(|(pos, pinned)| {
+                let walls = self.walls_around(pos);
+                if *pinned {
+                    self.is_wall(pos.x as i64, pos.y as i64)
+                } else if self.is_wall(pos.x as i64, pos.y as i64) {
+                    walls >= survival
+                } else {
+                    walls >= birth
+                }
+            })
+            .collect();
+    }
+
+    /// Flips every region of `wall` cells smaller than `min_size`
+    fn remove_regions(&mut self, wall: bool, min_size: usize) {
+        let mut visited = vec![false; self.walls.len()];
+        for start in 0..self.walls.len() {
+            if visited[start] || self.walls[start] != wall {
+                continue;
+            }
+            let mut region = vec![start];
+            let mut open = vec![start];
+            visited[start] = true;
+            while let Some(index) = open.pop() {
+                let x = index as u32 % self.dimensions.x;
+                let y = index as u32 / self.dimensions.x;
+                let mut neighbours = vec![];
+                if x > 0 {
+                    neighbours.push(index - 1);
+                }
+                if x + 1 < self.dimensions.x {
+                    neighbours.push(index + 1);
+                }
+                if y > 0 {
+                    neighbours.push(index - self.dimensions.x as usize);
+                }
+                if y + 1 < self.dimensions.y {
+                    neighbours.push(index + self.dimensions.x as usize);
+                }
+                for neighbour in neighbours {
+                    if !visited[neighbour] && self.walls[neighbour] == wall {
+                        visited[neighbour] = true;
+                        region.push(neighbour);
+                        open.push(neighbour);
+                    }
+                }
+            }
+            if region.len() < min_size {
+                for index in region.into_iter().filter(|index| !self.pinned[*index]) {
+                    self.walls[index] = !wall;
+                }
+            }
+        }
+    }
+
+    /// Sides of the cell at `pos` that have floor next to them
+    fn floor_sides(&self, pos: UVec2) -> u8 {
+        let (x, y) = (pos.x as i64, pos.y as i64);
+        let mut sides = 0;
+        if !self.is_wall(x, y - 1) {
+            sides |= CaveEdge::N;
+        }
+        if !self.is_wall(x + 1, y) {
+            sides |= CaveEdge::E;
+        }
+        if !self.is_wall(x, y + 1) {
+            sides |= CaveEdge::S;
+        }
+        if !self.is_wall(x - 1, y) {
+            sides |= CaveEdge::W;
+        }
+        sides
+    }
+}
+
+impl Terrain {
+    /// Fills every empty cell of `layer` with cave floors and walls
+    pub(crate) fn generate_cave(&mut self, layer: usize, settings: &CaveSettings) {
+        // Cells left from before, by regenerating a region for example, seed the automaton, so
+        // the new cells grow out of the cave around them
+        let map = &self.layers[layer].map;
+        let cells: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .collect();
+        let mut walls = vec![];
+        for pos in cells.iter() {
+            let random = self.rng.gen::<f32>() < settings.fill;
+            walls.push(
+                map.get(pos)
+                    .map_or(random, |module| module.id != settings.floor),
+            );
+        }
+        let mut cave = Cave {
+            dimensions: self.dimensions,
+            walls,
+            pinned: cells.iter().map(|pos| map.contains_key(pos)).collect(),
+        };
+        for _ in 0..settings.iterations {
+            cave.smooth(settings.birth, settings.survival);
+        }
+        cave.remove_regions(false, settings.min_floor_region);
+        cave.remove_regions(true, settings.min_wall_region);
+
+        for (pos, pinned) in cells.iter().zip(cave.pinned.iter()) {
+            let pos = *pos;
+            let id = if !cave.is_wall(pos.x as i64, pos.y as i64) {
+                settings.floor
+            } else {
+                let sides = cave.floor_sides(pos);
+                settings
+                    .edges
+                    .iter()
+                    .find(|edge| sides != 0 && edge.sides == sides)
+                    .map(|edge| edge.id)
+                    .unwrap_or(settings.wall)
+            };
+            if *pinned {
+                // Walls on the border of the new cells may need a different edge now
+                let old = self.layers[layer].map.get(&pos).map(|module| module.id);
+                let is_edge = old.map_or(false, |old| {
+                    old == settings.wall || settings.edges.iter().any(|edge| edge.id == old)
+                });
+                if !is_edge || old == Some(id) {
+                    continue;
+                }
+                self.clear(layer, pos);
+            }
+            self.place(layer, pos, id);
+        }
+    }
+}
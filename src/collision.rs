@@ -0,0 +1,124 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{Terrain, URect};
+
+/// Merged collision shapes of a generated terrain, added to its entity once generation finishes.
+///
+/// Edits, cleared cells and regenerated regions all finish generation again, which replaces the
+/// component with shapes for the new map. Until then it describes the map before the change.
+///
+/// Neighbouring cells are merged into as few rectangles as possible, so physics integrations can
+/// spawn one collider per rectangle instead of one per cell.
+#[derive(Component, Clone, Debug, Default)]
+pub struct TerrainColliders {
+    /// Cells with a [`TerrainModule::solid`](crate::TerrainModule::solid) module on any layer
+    pub solid: Vec<ColliderRect>,
+    /// Cells with a [`TerrainModule::water`](crate::TerrainModule::water) module on any layer
+    pub water: Vec<ColliderRect>,
+}
+
+/// Rectangle of cells, along with where it is relative to the terrain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColliderRect {
+    pub cells: URect,
+    pub center: Vec2,
+    pub size: Vec2,
+}
+
+impl Terrain {
+    /// Collision shapes of everything placed so far
+    pub fn colliders(&self) -> TerrainColliders {
+        let mut solid = HashSet::default();
+        let mut water = HashSet::default();
+        for layer in self.layers.iter() {
+            for (pos, module) in layer.map.iter() {
+                if module.solid {
+                    solid.insert(*pos);
+                }
+                if module.water {
+                    water.insert(*pos);
+                }
+            }
+        }
+        TerrainColliders {
+            solid: self.merge(&solid),
+            water: self.merge(&water),
+        }
+    }
+
+    /// Greedy meshing: grows each rectangle as far right as it goes, then as far down as the whole
+    /// row below is covered too
+    fn merge(&self, cells: &HashSet<UVec2>) -> Vec<ColliderRect> {
+        let mut merged = HashSet::default();
+        let mut rects = vec![];
+        for pos in URect::new(UVec2::ZERO, self.dimensions).positions() {
+            if !cells.contains(&pos) || merged.contains(&pos) {
+                continue;
+            }
+            let free = |cell: UVec2| cells.contains(&cell) && !merged.contains(&cell);
+            let mut max = pos + UVec2::ONE;
+            while max.x < self.dimensions.x && free(UVec2::new(max.x, pos.y)) {
+                max.x += 1;
+            }
+            while max.y < self.dimensions.y && (pos.x..max.x).all(|x| free(UVec2::new(x, max.y))) {
+                max.y += 1;
+            }
+            let area = URect::new(pos, max);
+            merged.extend(area.positions());
+            rects.push(self.collider_rect(area));
+        }
+        rects
+    }
+
+    fn collider_rect(&self, cells: URect) -> ColliderRect {
+        // Tiles are centered on their cell, so the middle is half a cell before `max`
+        let middle = (cells.min.as_vec2() + cells.max.as_vec2() - Vec2::ONE) / 2.0;
+        ColliderRect {
+            cells,
+            center: Vec2::new(middle.x, -middle.y) * self.module_dimensions,
+            size: (cells.max - cells.min).as_vec2() * self.module_dimensions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, named},
+        GenerationType, TerrainModule,
+    };
+
+    #[test]
+    fn merges_solid_cells_into_rectangles() {
+        let mut terrain = testing::terrain(GenerationType::WaveCollapse, UVec2::new(4, 3), &[])
+            .with_module(TerrainModule {
+                solid: true,
+                ..named("wall")
+            });
+        let wall = testing::id(&terrain, "wall");
+        // ##..
+        // ##.#
+        // ...#
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1), (3, 1), (3, 2)] {
+            assert!(terrain.place(0, UVec2::new(x, y), wall));
+        }
+        let colliders = terrain.colliders();
+        assert!(colliders.water.is_empty());
+        assert_eq!(
+            colliders.solid,
+            vec![
+                ColliderRect {
+                    cells: URect::new(UVec2::new(0, 0), UVec2::new(2, 2)),
+                    center: Vec2::new(8.0, -8.0),
+                    size: Vec2::new(32.0, 32.0),
+                },
+                ColliderRect {
+                    cells: URect::new(UVec2::new(3, 1), UVec2::new(4, 3)),
+                    center: Vec2::new(48.0, -24.0),
+                    size: Vec2::new(16.0, 32.0),
+                },
+            ]
+        );
+    }
+}
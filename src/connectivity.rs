@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_inspector_egui::Inspectable;
+
+use crate::{get_adjacent_positions, Terrain, URect};
+
+/// How many times a layer is repaired before giving up on connecting it
+const MAX_CONNECT_ATTEMPTS: u32 = 10;
+
+/// Which [`TerrainModule::passable`](crate::TerrainModule::passable) cells of a layer have to be
+/// reachable from each other.
+///
+/// Checked once the layer is generated. Cells in the way are cleared and solved again with only
+/// passable modules, so the layer's rules need to allow passable modules to line up.
+#[derive(Clone, Debug, Inspectable)]
+pub enum Connectivity {
+    /// All passable cells form one region
+    All,
+    /// The given cells are passable and connected to each other
+    Points(Vec<UVec2>),
+}
+
+impl Default for Connectivity {
+    fn default() -> Self {
+        Connectivity::All
+    }
+}
+
+impl Terrain {
+    /// Requires the passable cells of the last added layer to be connected
+    pub fn with_connectivity(mut self, connectivity: Connectivity) -> Terrain {
+        self.last_layer_mut().connectivity = Some(connectivity);
+        self
+    }
+
+    /// Connected regions of passable cells on `layer`
+    pub(crate) fn passable_regions(&self, layer: usize) -> Vec<HashSet<UVec2>> {
+        let map = &self.layers[layer].map;
+        let mut visited = HashSet::default();
+        let mut regions = vec![];
+        // Grid order rather than map order, so regions come out the same for the same seed
+        for start in URect::new(UVec2::ZERO, self.dimensions).positions() {
+            let passable = map.get(&start).map_or(false, |module| module.passable);
+            if !passable || visited.contains(&start) {
+                continue;
+            }
+            let mut region = HashSet::default();
+            let mut open = vec![start];
+            visited.insert(start);
+            while let Some(pos) = open.pop() {
+                region.insert(pos);
+                for adjacent in get_adjacent_positions(&pos) {
+                    let passable = map.get(&adjacent).map_or(false, |module| module.passable);
+                    if passable && visited.insert(adjacent) {
+                        open.push(adjacent);
+                    }
+                }
+            }
+            regions.push(region);
+        }
+        regions
+    }
+
+    /// Clears the cells between disconnected regions of the current layer so they are solved
+    /// again as passable, returns whether anything was cleared
+    pub(crate) fn repair_connectivity(&mut self) -> bool {
+        let layer = self.layer;
+        let connectivity = match &self.layers[layer].connectivity {
+            Some(connectivity) => connectivity.clone(),
+            None => return false,
+        };
+        let regions = self.passable_regions(layer);
+        let region_of = |pos: UVec2| regions.iter().position(|region| region.contains(&pos));
+        let (main, targets): (Option<usize>, Vec<UVec2>) = match &connectivity {
+            Connectivity::All => {
+                let largest = (0..regions.len()).max_by_key(|region| regions[*region].len());
+                let targets = regions
+                    .iter()
+                    .enumerate()
+                    .filter(|(region, _)| Some(*region) != largest)
+                    // Unwrap is fine because regions are never empty
+                    .map(|(_, region)| *region.iter().min_by_key(|pos| (pos.y, pos.x)).unwrap())
+                    .collect();
+                (largest, targets)
+            }
+            Connectivity::Points(points) => match points.split_first() {
+                Some((first, rest)) => {
+                    let main = region_of(*first);
+                    let targets = rest
+                        .iter()
+                        .filter(|point| main.is_none() || region_of(**point) != main)
+                        .copied()
+                        .collect();
+                    (main, targets)
+                }
+                None => (None, vec![]),
+            },
+        };
+        let unconnected_point = match &connectivity {
+            Connectivity::Points(points) => points.first().filter(|_| main.is_none()).copied(),
+            Connectivity::All => None,
+        };
+        if targets.is_empty() && unconnected_point.is_none() {
+            self.layers[layer].connect_attempts = 0;
+            self.layers[layer].required_passable.clear();
+            return false;
+        }
+
+        let terrain_layer = &self.layers[layer];
+        let name = terrain_layer.name.clone();
+        if !terrain_layer.gen_type.is_wave_collapse()
+            || terrain_layer.connect_attempts >= MAX_CONNECT_ATTEMPTS
+        {
+            warn!("Couldn't connect the passable cells of layer {}", name);
+            self.layers[layer].connect_attempts = 0;
+            self.layers[layer].required_passable.clear();
+            return false;
+        }
+
+        let mut path = vec![];
+        path.extend(unconnected_point);
+        if let Some(main) = main {
+            for target in targets {
+                path.extend(self.path_to_region(target, &regions[main]));
+            }
+        } else {
+            path.extend(targets);
+        }
+        let mut cleared = vec![];
+        for pos in path.iter() {
+            let passable = self.layers[layer]
+                .map
+                .get(pos)
+                .map_or(false, |module| module.passable);
+            if passable {
+                continue;
+            }
+            self.layers[layer].required_passable.insert(*pos);
+            if !cleared.contains(pos) {
+                cleared.push(*pos);
+            }
+            // Gives the solver room to fit the new passable cell in
+            for adjacent in get_adjacent_positions(pos) {
+                if self.layers[layer].map.contains_key(&adjacent)
+                    && !path.contains(&adjacent)
+                    && !cleared.contains(&adjacent)
+                {
+                    cleared.push(adjacent);
+                }
+            }
+        }
+        for pos in cleared.iter() {
+            self.clear(layer, *pos);
+        }
+        self.layers[layer].connect_attempts += 1;
+        self.solve_cleared(&cleared);
+        true
+    }
+
+    /// Shortest path of cells from `from` to any cell of `region`
+    fn path_to_region(&self, from: UVec2, region: &HashSet<UVec2>) -> Vec<UVec2> {
+        let mut came_from = HashMap::default();
+        let mut open = VecDeque::from([from]);
+        came_from.insert(from, from);
+        while let Some(pos) = open.pop_front() {
+            if region.contains(&pos) {
+                let mut path = vec![pos];
+                let mut current = pos;
+                while current != from {
+                    current = came_from[&current];
+                    path.push(current);
+                }
+                return path;
+            }
+            for adjacent in get_adjacent_positions(&pos) {
+                let in_bounds = adjacent.x < self.dimensions.x && adjacent.y < self.dimensions.y;
+                if in_bounds && !came_from.contains_key(&adjacent) {
+                    came_from.insert(adjacent, pos);
+                    open.push_back(adjacent);
+                }
+            }
+        }
+        vec![]
+    }
+}
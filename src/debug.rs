@@ -0,0 +1,123 @@
+use bevy::{prelude::*, render::texture::DEFAULT_IMAGE_HANDLE, utils::HashMap};
+
+use crate::{Terrain, URect};
+
+/// Add next to a [`Terrain`] to draw its generation as it happens.
+///
+/// Placed modules are drawn as usual, undecided cells are tinted from blue (few modules allowed)
+/// to green (every module allowed), and cells where no module is allowed anymore are red.
+#[derive(Component)]
+pub struct TerrainDebug {
+    pub paused: bool,
+    pub pause_key: KeyCode,
+    pub step_key: KeyCode,
+    pub(crate) step: bool,
+    cells: HashMap<(usize, UVec2), Entity>,
+}
+
+impl Default for TerrainDebug {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            pause_key: KeyCode::P,
+            step_key: KeyCode::N,
+            step: false,
+            cells: HashMap::new(),
+        }
+    }
+}
+
+impl TerrainDebug {
+    pub fn paused() -> Self {
+        Self {
+            paused: true,
+            ..default()
+        }
+    }
+
+    /// Does nothing in apps without keyboard input, like headless servers
+    pub(crate) fn input(keys: Option<Res<Input<KeyCode>>>, mut debugs: Query<&mut TerrainDebug>) {
+        let keys = match keys {
+            Some(keys) => keys,
+            None => return,
+        };
+        for mut debug in debugs.iter_mut() {
+            if keys.just_pressed(debug.pause_key) {
+                debug.paused = !debug.paused;
+            }
+            if keys.just_pressed(debug.step_key) {
+                debug.step = true;
+            }
+        }
+    }
+
+    pub(crate) fn draw(
+        mut commands: Commands,
+        mut terrains: Query<(Entity, &Terrain, &mut TerrainDebug)>,
+        mut sprites: Query<(&mut Sprite, &mut Handle<Image>)>,
+    ) {
+        for (entity, terrain, mut debug) in terrains.iter_mut() {
+            if terrain.is_finished() {
+                for (_, cell) in debug.cells.drain() {
+                    commands.entity(cell).despawn_recursive();
+                }
+                continue;
+            }
+            for (layer, terrain_layer) in terrain.layers.iter().enumerate() {
+                let module_count = terrain_layer.modules.len().max(1);
+                for pos in URect::new(UVec2::ZERO, terrain.dimensions).positions() {
+                    let (sprite, texture) = match terrain_layer.map.get(&pos) {
+                        Some(module) => (Sprite::default(), module.image.clone()),
+                        // Only the layer being generated has undecided cells worth showing
+                        None if layer != terrain.layer => {
+                            let sprite = Sprite {
+                                color: Color::NONE,
+                                ..default()
+                            };
+                            (sprite, DEFAULT_IMAGE_HANDLE.typed())
+                        }
+                        None => {
+                            let allowed = terrain.allowed(layer, pos).len();
+                            let color = if allowed == 0 {
+                                Color::RED
+                            } else {
+                                let entropy = allowed as f32 / module_count as f32;
+                                Color::rgb(0.0, entropy, 1.0 - entropy)
+                            };
+                            let sprite = Sprite {
+                                color,
+                                custom_size: Some(terrain.module_dimensions),
+                                ..default()
+                            };
+                            (sprite, DEFAULT_IMAGE_HANDLE.typed())
+                        }
+                    };
+                    match debug.cells.get(&(layer, pos)).copied() {
+                        Some(cell) => {
+                            if let Ok((mut current_sprite, mut current_texture)) =
+                                sprites.get_mut(cell)
+                            {
+                                *current_sprite = sprite;
+                                *current_texture = texture;
+                            }
+                        }
+                        None => {
+                            let transform = terrain.tile_transform(layer, pos);
+                            commands.entity(entity).with_children(|parent| {
+                                let cell = parent
+                                    .spawn_bundle(SpriteBundle {
+                                        sprite,
+                                        transform,
+                                        texture,
+                                        ..default()
+                                    })
+                                    .id();
+                                debug.cells.insert((layer, pos), cell);
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{Terrain, TerrainModuleId, URect};
+
+/// Settings for [`GenerationType::Dungeon`](crate::GenerationType::Dungeon).
+///
+/// The terrain is split in two over and over, a room is placed in every leaf of the splits and
+/// the rooms of both halves of each split are connected with a corridor, so every room can be
+/// reached from every other room.
+#[derive(Clone, Debug, Inspectable)]
+pub struct DungeonSettings {
+    /// How many times the terrain is split at most
+    pub depth: usize,
+    /// Splits never make a part smaller than this, on either axis
+    pub min_leaf: u32,
+    /// Rooms are at least this big, on either axis, not counting their walls
+    pub min_room: u32,
+    pub floor: TerrainModuleId,
+    pub wall: TerrainModuleId,
+    pub door: TerrainModuleId,
+    /// Cells outside of rooms, corridors and walls
+    pub void: TerrainModuleId,
+    /// Leaves the floor of rooms empty for wave collapse to fill, instead of using `floor`
+    pub solve_rooms: bool,
+}
+
+impl Default for DungeonSettings {
+    fn default() -> Self {
+        Self {
+            depth: 4,
+            min_leaf: 8,
+            min_room: 4,
+            floor: default(),
+            wall: default(),
+            door: default(),
+            void: default(),
+            solve_rooms: false,
+        }
+    }
+}
+
+impl DungeonSettings {
+    pub fn new(
+        floor: TerrainModuleId,
+        wall: TerrainModuleId,
+        door: TerrainModuleId,
+        void: TerrainModuleId,
+    ) -> Self {
+        Self {
+            floor,
+            wall,
+            door,
+            void,
+            ..default()
+        }
+    }
+}
+
+/// The rooms of a generated dungeon and how they are connected
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DungeonLayout {
+    /// Floor of each room, without its walls
+    pub rooms: Vec<URect>,
+    /// Indices into `rooms` of rooms connected by a corridor
+    pub connections: Vec<(usize, usize)>,
+    /// Cells of the corridor of each connection, from the first room to the second
+    #[serde(default)]
+    pub corridors: Vec<Vec<UVec2>>,
+    pub doors: Vec<UVec2>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DungeonCell {
+    Void,
+    Room,
+    Corridor,
+    Door,
+    Wall,
+}
+
+impl Terrain {
+    /// Lays out a dungeon on `layer` and fills its empty cells, returns whether room floors are
+    /// left for wave collapse
+    pub(crate) fn generate_dungeon(&mut self, layer: usize, settings: &DungeonSettings) -> bool {
+        let layout = self.lay_out_dungeon(settings);
+        let unsolved = self.fill_dungeon(layer, settings, &layout);
+        self.layers[layer].dungeon = Some(layout);
+
+        if unsolved.is_empty() {
+            return false;
+        }
+        self.layer = layer;
+        self.solve_cleared(&unsolved);
+        true
+    }
+
+    /// Splits the terrain into rooms and routes the corridors between them
+    fn lay_out_dungeon(&mut self, settings: &DungeonSettings) -> DungeonLayout {
+        let mut layout = DungeonLayout::default();
+        self.split(
+            URect::new(UVec2::ZERO, self.dimensions),
+            settings.depth,
+            settings,
+            &mut layout,
+        );
+        for (a, b) in layout.connections.clone() {
+            let from = center(layout.rooms[a]);
+            let to = center(layout.rooms[b]);
+            let corner = if self.rng.gen() {
+                UVec2::new(to.x, from.y)
+            } else {
+                UVec2::new(from.x, to.y)
+            };
+            let mut route = line(from, corner);
+            // The corner starts the second leg as well
+            route.extend(line(corner, to).into_iter().skip(1));
+            layout.corridors.push(route);
+        }
+        // Doors go where a corridor leaves or enters a room, not all along a room's walls
+        let in_room = |pos: &UVec2| layout.rooms.iter().any(|room| room.contains(*pos));
+        let mut doors = vec![];
+        for route in layout.corridors.iter() {
+            for (index, pos) in route.iter().enumerate() {
+                if in_room(pos) {
+                    continue;
+                }
+                let is_room = |index: Option<usize>| {
+                    index
+                        .and_then(|index| route.get(index))
+                        .map_or(false, in_room)
+                };
+                let at_room = is_room(index.checked_sub(1)) || is_room(Some(index + 1));
+                if at_room && !doors.contains(pos) {
+                    doors.push(*pos);
+                }
+            }
+        }
+        layout.doors = doors;
+        layout
+    }
+
+    /// Places the modules of `layout` in the empty cells of `layer`, returns the room cells left
+    /// for wave collapse
+    pub(crate) fn fill_dungeon(
+        &mut self,
+        layer: usize,
+        settings: &DungeonSettings,
+        layout: &DungeonLayout,
+    ) -> Vec<UVec2> {
+        let mut cells = HashMap::default();
+        for room in layout.rooms.iter() {
+            for pos in room.positions() {
+                cells.insert(pos, DungeonCell::Room);
+            }
+        }
+        for pos in layout.corridors.iter().flatten() {
+            cells.entry(*pos).or_insert(DungeonCell::Corridor);
+        }
+        for pos in layout.doors.iter() {
+            cells.insert(*pos, DungeonCell::Door);
+        }
+        let open: Vec<UVec2> = cells.keys().copied().collect();
+        for pos in open {
+            for neighbour in surrounding(pos) {
+                if neighbour.x < self.dimensions.x && neighbour.y < self.dimensions.y {
+                    cells.entry(neighbour).or_insert(DungeonCell::Wall);
+                }
+            }
+        }
+
+        let mut unsolved = vec![];
+        for pos in URect::new(UVec2::ZERO, self.dimensions).positions() {
+            if self.layers[layer].map.contains_key(&pos) {
+                continue;
+            }
+            let id = match cells.get(&pos).copied().unwrap_or(DungeonCell::Void) {
+                DungeonCell::Room if settings.solve_rooms => {
+                    unsolved.push(pos);
+                    continue;
+                }
+                DungeonCell::Room | DungeonCell::Corridor => settings.floor,
+                DungeonCell::Door => settings.door,
+                DungeonCell::Wall => settings.wall,
+                DungeonCell::Void => settings.void,
+            };
+            self.place(layer, pos, id);
+        }
+        unsolved
+    }
+
+    /// Splits `area` in two until it gets too small or `depth` runs out, placing a room in each
+    /// leaf. Returns the indices of the rooms placed inside `area`.
+    fn split(
+        &mut self,
+        area: URect,
+        depth: usize,
+        settings: &DungeonSettings,
+        layout: &mut DungeonLayout,
+    ) -> Vec<usize> {
+        let size = area.max - area.min;
+        let can_split_x = size.x >= settings.min_leaf * 2;
+        let can_split_y = size.y >= settings.min_leaf * 2;
+        if depth == 0 || !(can_split_x || can_split_y) {
+            return self
+                .place_room(area, settings, layout)
+                .into_iter()
+                .collect();
+        }
+        let split_x = match (can_split_x, can_split_y) {
+            (true, false) => true,
+            (false, true) => false,
+            _ if size.x as f32 > size.y as f32 * 1.25 => true,
+            _ if size.y as f32 > size.x as f32 * 1.25 => false,
+            _ => self.rng.gen(),
+        };
+        let (first, second) = if split_x {
+            let at = area.min.x
+                + self
+                    .rng
+                    .gen_range(settings.min_leaf..=size.x - settings.min_leaf);
+            (
+                URect::new(area.min, UVec2::new(at, area.max.y)),
+                URect::new(UVec2::new(at, area.min.y), area.max),
+            )
+        } else {
+            let at = area.min.y
+                + self
+                    .rng
+                    .gen_range(settings.min_leaf..=size.y - settings.min_leaf);
+            (
+                URect::new(area.min, UVec2::new(area.max.x, at)),
+                URect::new(UVec2::new(area.min.x, at), area.max),
+            )
+        };
+        let mut first = self.split(first, depth - 1, settings, layout);
+        let second = self.split(second, depth - 1, settings, layout);
+
+        // Connects the closest pair of rooms across the split
+        let closest = first
+            .iter()
+            .flat_map(|a| second.iter().map(move |b| (*a, *b)))
+            .min_by_key(|(a, b)| {
+                let a = center(layout.rooms[*a]).as_ivec2();
+                let b = center(layout.rooms[*b]).as_ivec2();
+                (a.x - b.x).abs() + (a.y - b.y).abs()
+            });
+        if let Some(connection) = closest {
+            layout.connections.push(connection);
+        }
+        first.extend(second);
+        first
+    }
+
+    /// Places a room inside `leaf`, leaving space for its walls
+    fn place_room(
+        &mut self,
+        leaf: URect,
+        settings: &DungeonSettings,
+        layout: &mut DungeonLayout,
+    ) -> Option<usize> {
+        let size = leaf.max - leaf.min;
+        if size.x < settings.min_room + 2 || size.y < settings.min_room + 2 {
+            return None;
+        }
+        let width = self.rng.gen_range(settings.min_room..=size.x - 2);
+        let height = self.rng.gen_range(settings.min_room..=size.y - 2);
+        let x = leaf.min.x + 1 + self.rng.gen_range(0..=size.x - 2 - width);
+        let y = leaf.min.y + 1 + self.rng.gen_range(0..=size.y - 2 - height);
+        let min = UVec2::new(x, y);
+        layout
+            .rooms
+            .push(URect::new(min, min + UVec2::new(width, height)));
+        Some(layout.rooms.len() - 1)
+    }
+}
+
+fn center(rect: URect) -> UVec2 {
+    (rect.min + rect.max) / 2
+}
+
+/// Cells from `from` to `to` in order, which have to share a row or a column
+fn line(from: UVec2, to: UVec2) -> Vec<UVec2> {
+    let mut cells: Vec<UVec2> = URect::new(from.min(to), from.max(to) + UVec2::ONE)
+        .positions()
+        .collect();
+    if from != from.min(to) {
+        cells.reverse();
+    }
+    cells
+}
+
+fn surrounding(pos: UVec2) -> Vec<UVec2> {
+    let mut posses = vec![];
+    for y in pos.y.saturating_sub(1)..=pos.y + 1 {
+        for x in pos.x.saturating_sub(1)..=pos.x + 1 {
+            if UVec2::new(x, y) != pos {
+                posses.push(UVec2::new(x, y));
+            }
+        }
+    }
+    posses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_adjacent_positions, testing, GenerationType};
+
+    fn dungeon(seed: u64) -> (Terrain, DungeonSettings) {
+        let mut terrain = testing::terrain(
+            GenerationType::Dungeon(default()),
+            UVec2::splat(40),
+            &["floor", "wall", "door", "void"],
+        )
+        .with_seed(seed);
+        let id = |name| testing::id(&terrain, name);
+        let settings = DungeonSettings::new(id("floor"), id("wall"), id("door"), id("void"));
+        terrain.generate_dungeon(0, &settings);
+        (terrain, settings)
+    }
+
+    #[test]
+    fn rooms_fit_and_are_all_connected() {
+        for seed in 0..10 {
+            let (terrain, settings) = dungeon(seed);
+            let layout = terrain.layers[0].dungeon().unwrap();
+            assert!(layout.rooms.len() > 1);
+            for (index, room) in layout.rooms.iter().enumerate() {
+                let size = room.max - room.min;
+                assert!(size.x >= settings.min_room && size.y >= settings.min_room);
+                // Room for walls on every side
+                assert!(room.min.x >= 1 && room.min.y >= 1);
+                assert!(room.max.x < 40 && room.max.y < 40);
+                for other in layout.rooms[index + 1..].iter() {
+                    assert!(!room.positions().any(|pos| other.contains(pos)));
+                }
+                for pos in room.positions() {
+                    let module = terrain.layers[0].module_at(pos).unwrap();
+                    assert_eq!(module.id, settings.floor);
+                }
+            }
+            // Every split connects its two halves once, which joins all rooms into one tree
+            assert_eq!(layout.connections.len(), layout.rooms.len() - 1);
+            let mut group: Vec<usize> = (0..layout.rooms.len()).collect();
+            for (a, b) in layout.connections.iter() {
+                let (old, new) = (group[*a], group[*b]);
+                for room in group.iter_mut().filter(|room| **room == old) {
+                    *room = new;
+                }
+            }
+            assert!(group.iter().all(|room| *room == group[0]));
+        }
+    }
+
+    #[test]
+    fn doors_are_next_to_rooms() {
+        for seed in 0..10 {
+            let (terrain, settings) = dungeon(seed);
+            let layout = terrain.layers[0].dungeon().unwrap();
+            assert!(!layout.doors.is_empty());
+            let in_room = |pos: UVec2| layout.rooms.iter().any(|room| room.contains(pos));
+            for door in layout.doors.iter() {
+                assert!(!in_room(*door));
+                assert!(get_adjacent_positions(door).into_iter().any(in_room));
+                let module = terrain.layers[0].module_at(*door).unwrap();
+                assert_eq!(module.id, settings.door);
+            }
+        }
+    }
+}
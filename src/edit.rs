@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+
+use crate::{
+    footprint::{edge_fits, footprint},
+    GenerationState, Terrain, TerrainModule, TerrainModuleId,
+};
+
+/// How [`Terrain::set_cell`] deals with a module that doesn't fit its neighbours
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellEdit {
+    /// Places the module whether it fits or not
+    Force,
+    /// Only places the module if it fits
+    Checked,
+    /// Places the module and generates the neighbours that don't fit it again
+    Repair,
+}
+
+impl Terrain {
+    /// Whether the module with `id` could be placed at `pos` on `layer`, according to its own rule
+    /// and the rules of the modules around it
+    pub fn can_place(&self, layer: &str, pos: UVec2, id: TerrainModuleId) -> bool {
+        let layer = match self.layer_index(layer) {
+            Some(layer) => layer,
+            None => return false,
+        };
+        match self.layers[layer].module(id) {
+            Some(module) => {
+                self.in_bounds(pos)
+                    && self.footprint_free(layer, pos, module, true)
+                    && self.misfits(layer, pos, module).is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Places the module with `id` at `pos` on `layer`, returns whether it was placed. Modules
+    /// that would leave the grid or cover other cells than the one at `pos` are never placed.
+    pub fn set_cell(
+        &mut self,
+        layer: &str,
+        pos: UVec2,
+        id: TerrainModuleId,
+        edit: CellEdit,
+    ) -> bool {
+        let layer = match self.layer_index(layer) {
+            Some(layer) => layer,
+            None => return false,
+        };
+        let module = match self.layers[layer].module(id) {
+            Some(module) if self.in_bounds(pos) => module.clone(),
+            _ => return false,
+        };
+        if !self.footprint_free(layer, pos, &module, true) {
+            return false;
+        }
+        let misfits = self.misfits(layer, pos, &module);
+        if edit == CellEdit::Checked && !misfits.is_empty() {
+            return false;
+        }
+        self.clear(layer, pos);
+        self.insert(layer, pos, module);
+        // When the module's own rule fails, any of its neighbours could be the reason
+        let neighbours: Vec<UVec2> = if misfits.contains(&pos) {
+            self.neighbourhood(layer, pos)
+                .into_iter()
+                .filter(|adjacent| self.layers[layer].map.contains_key(adjacent))
+                .collect()
+        } else {
+            misfits
+        };
+        if edit == CellEdit::Repair && !neighbours.is_empty() {
+            for neighbour in neighbours.iter() {
+                self.clear(layer, *neighbour);
+            }
+            self.layer = layer;
+            self.solve_cleared(&neighbours);
+        } else if self.is_finished() {
+            self.state = GenerationState::Finished;
+        }
+        true
+    }
+
+    /// Removes the module at `pos` on `layer`, `repair` generates the cell again to fit its
+    /// neighbours
+    pub fn clear_cell(&mut self, layer: &str, pos: UVec2, repair: bool) {
+        let layer = match self.layer_index(layer) {
+            Some(layer) => layer,
+            None => return,
+        };
+        if !self.layers[layer].map.contains_key(&pos) {
+            return;
+        }
+        self.clear(layer, pos);
+        if repair {
+            self.layer = layer;
+            self.solve_cleared(&[pos]);
+        } else if self.is_finished() {
+            // Updates the navigation grid and colliders of the cleared cells
+            self.state = GenerationState::Finished;
+        }
+    }
+
+    fn in_bounds(&self, pos: UVec2) -> bool {
+        pos.x < self.dimensions.x && pos.y < self.dimensions.y
+    }
+
+    /// Cells whose rule would fail if `module` replaced the module at `pos`, including `pos`
+    /// itself. Multi-cell modules are checked along the whole outer edge of their footprint.
+    pub(crate) fn misfits(&self, layer: usize, pos: UVec2, module: &TerrainModule) -> Vec<UVec2> {
+        let terrain_layer = &self.layers[layer];
+        let mut map = terrain_layer.map.clone();
+        let anchor = terrain_layer.anchor(pos);
+        if let Some(old) = terrain_layer.map.get(&anchor) {
+            for cell in footprint(anchor, old).positions() {
+                map.remove(&cell);
+            }
+        }
+        let area = footprint(pos, module);
+        for cell in area.positions() {
+            map.insert(cell, module.clone());
+        }
+        let mut misfits = vec![];
+        let context = self.rule_context(layer, pos, &map);
+        let fits = if module.is_multi_cell() {
+            module.generation_rule.allows(&context) && edge_fits(&map, pos, module)
+        } else {
+            module.fits(&context)
+        };
+        if !fits {
+            misfits.push(pos);
+        }
+        for cell in area.positions() {
+            for adjacent in self.neighbourhood(layer, cell) {
+                if area.contains(adjacent) || misfits.contains(&adjacent) {
+                    continue;
+                }
+                if let Some(neighbour) = map.get(&adjacent) {
+                    let context = self.rule_context(layer, adjacent, &map);
+                    if !neighbour.fits(&context) {
+                        misfits.push(adjacent);
+                    }
+                }
+            }
+        }
+        misfits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, finish, named},
+        GenerationType, Sockets, URect,
+    };
+
+    /// Finished terrain of grass, with water and shore modules that fit next to water
+    fn meadow() -> Terrain {
+        let mut terrain = testing::terrain(GenerationType::WaveCollapse, UVec2::new(4, 4), &[])
+            .with_module(TerrainModule {
+                sockets: Sockets::all("grass"),
+                ..named("grass")
+            })
+            .with_module(TerrainModule {
+                sockets: Sockets::all("water"),
+                ..named("water")
+            })
+            .with_module(named("shore"))
+            .with_seed(6);
+        let grass = testing::id(&terrain, "grass");
+        for pos in URect::new(UVec2::ZERO, terrain.dimensions()).positions() {
+            terrain.place(0, pos, grass);
+        }
+        terrain.state = GenerationState::Done;
+        terrain
+    }
+
+    fn fits_everywhere(terrain: &Terrain) -> bool {
+        URect::new(UVec2::ZERO, terrain.dimensions())
+            .positions()
+            .all(|pos| match terrain.layers[0].module_at(pos) {
+                Some(module) => terrain.misfits(0, pos, module).is_empty(),
+                None => false,
+            })
+    }
+
+    #[test]
+    fn checked_edits_only_place_fitting_modules() {
+        let mut terrain = meadow();
+        let water = testing::id(&terrain, "water");
+        let shore = testing::id(&terrain, "shore");
+        assert!(!terrain.can_place("ground", UVec2::ONE, water));
+        assert!(!terrain.set_cell("ground", UVec2::ONE, water, CellEdit::Checked));
+        assert_eq!(testing::name_at(&terrain, UVec2::ONE), Some("grass"));
+        assert!(terrain.set_cell("ground", UVec2::ONE, shore, CellEdit::Checked));
+        assert_eq!(testing::name_at(&terrain, UVec2::ONE), Some("shore"));
+        assert!(matches!(terrain.state, GenerationState::Finished));
+    }
+
+    #[test]
+    fn forced_edits_are_placed_as_they_are() {
+        let mut terrain = meadow();
+        let water = testing::id(&terrain, "water");
+        assert!(terrain.set_cell("ground", UVec2::ONE, water, CellEdit::Force));
+        assert_eq!(testing::name_at(&terrain, UVec2::ONE), Some("water"));
+        assert!(!fits_everywhere(&terrain));
+        assert!(!terrain.set_cell("ground", UVec2::new(4, 0), water, CellEdit::Force));
+        assert!(!terrain.set_cell("sky", UVec2::ONE, water, CellEdit::Force));
+    }
+
+    #[test]
+    fn repaired_edits_solve_their_neighbours_again() {
+        let mut terrain = meadow();
+        let water = testing::id(&terrain, "water");
+        assert!(terrain.set_cell("ground", UVec2::ONE, water, CellEdit::Repair));
+        finish(&mut terrain);
+        assert_eq!(testing::name_at(&terrain, UVec2::ONE), Some("water"));
+        assert!(fits_everywhere(&terrain));
+    }
+
+    #[test]
+    fn cleared_cells_are_only_solved_again_when_repaired() {
+        let mut terrain = meadow();
+        terrain.clear_cell("ground", UVec2::ONE, false);
+        assert_eq!(testing::name_at(&terrain, UVec2::ONE), None);
+        assert!(matches!(terrain.state, GenerationState::Finished));
+        terrain.state = GenerationState::Done;
+        terrain.clear_cell("ground", UVec2::new(2, 2), true);
+        finish(&mut terrain);
+        assert!(testing::name_at(&terrain, UVec2::new(2, 2)).is_some());
+    }
+}
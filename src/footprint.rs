@@ -0,0 +1,189 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{Side, Terrain, TerrainLayer, TerrainModule, TerrainModuleId, URect};
+
+/// Cells covered by `module` when it is placed at `pos`
+pub(crate) fn footprint(pos: UVec2, module: &TerrainModule) -> URect {
+    URect::new(pos, pos + module.size.max(UVec2::ONE))
+}
+
+/// Whether the sockets of `module` placed at `pos` match the modules of `map` around the outer
+/// edge of its footprint
+pub(crate) fn edge_fits(
+    map: &HashMap<UVec2, TerrainModule>,
+    pos: UVec2,
+    module: &TerrainModule,
+) -> bool {
+    let area = footprint(pos, module);
+    area.positions().all(|cell| {
+        Side::ALL.iter().all(|side| {
+            let outside = match side {
+                Side::North => cell.y.checked_sub(1).map(|y| UVec2::new(cell.x, y)),
+                Side::East => Some(cell + UVec2::X),
+                Side::South => Some(cell + UVec2::Y),
+                Side::West => cell.x.checked_sub(1).map(|x| UVec2::new(x, cell.y)),
+            };
+            let neighbour = match outside.filter(|outside| !area.contains(*outside)) {
+                Some(outside) => map.get(&outside),
+                None => return true,
+            };
+            let own = module.sockets.side(*side);
+            let other = match neighbour {
+                Some(neighbour) => neighbour.sockets.side(side.opposite()),
+                None => return true,
+            };
+            own.is_empty() || other.is_empty() || own.iter().any(|tag| other.contains(tag))
+        })
+    })
+}
+
+impl TerrainModule {
+    pub fn is_multi_cell(&self) -> bool {
+        self.size.x > 1 || self.size.y > 1
+    }
+
+    /// How many cells the module covers
+    pub fn cells(&self) -> usize {
+        let size = self.size.max(UVec2::ONE);
+        (size.x * size.y) as usize
+    }
+}
+
+impl TerrainLayer {
+    /// Cell the module covering `pos` was placed at, `pos` itself for single cell modules
+    pub fn anchor(&self, pos: UVec2) -> UVec2 {
+        self.anchors.get(&pos).copied().unwrap_or(pos)
+    }
+}
+
+impl Terrain {
+    /// Whether all of the cells `module` would cover from `pos` are on the grid and free.
+    /// `replace` also allows the cells of the module covering `pos`, which is cleared first.
+    pub(crate) fn footprint_free(
+        &self,
+        layer: usize,
+        pos: UVec2,
+        module: &TerrainModule,
+        replace: bool,
+    ) -> bool {
+        let area = footprint(pos, module);
+        if area.max.x > self.dimensions.x || area.max.y > self.dimensions.y {
+            return false;
+        }
+        let terrain_layer = &self.layers[layer];
+        let replaced = terrain_layer.map.get(&pos).map(|old| {
+            let anchor = terrain_layer.anchor(pos);
+            footprint(anchor, old)
+        });
+        area.positions().all(|cell| {
+            !terrain_layer.map.contains_key(&cell)
+                || (replace && replaced.map_or(false, |replaced| replaced.contains(cell)))
+        })
+    }
+
+    /// Places `module` at `pos` if its whole footprint is on the grid and free, returns whether
+    /// it was placed. Every placement goes through here, so modules never overlap.
+    pub(crate) fn insert(&mut self, layer: usize, pos: UVec2, module: TerrainModule) -> bool {
+        if !self.footprint_free(layer, pos, &module, false) {
+            return false;
+        }
+        self.layers[layer].insert(pos, module);
+        true
+    }
+
+    /// Places the module with `id` at `pos`, for generators that pick modules by id
+    pub(crate) fn place(&mut self, layer: usize, pos: UVec2, id: TerrainModuleId) -> bool {
+        match self.layers[layer].module(id).cloned() {
+            Some(module) => self.insert(layer, pos, module),
+            None => {
+                warn!("Layer {} has no module {}", self.layers[layer].name, id);
+                false
+            }
+        }
+    }
+
+    /// Whether all of the cells `module` would cover from `pos` are free, and its sockets match
+    /// the modules around its outer edge
+    pub(crate) fn footprint_fits(&self, layer: usize, pos: UVec2, module: &TerrainModule) -> bool {
+        if !self.footprint_free(layer, pos, module, false) {
+            return false;
+        }
+        if !module.is_multi_cell() {
+            return true;
+        }
+        edge_fits(&self.layers[layer].map, pos, module)
+    }
+
+    /// Moves the sprite of a multi-cell module from its anchor to the middle of its footprint
+    pub(crate) fn footprint_offset(&self, module: &TerrainModule) -> Vec3 {
+        let extra = (module.size.max(UVec2::ONE) - UVec2::ONE).as_vec2() / 2.0;
+        Vec3::new(
+            extra.x * self.module_dimensions.x,
+            -extra.y * self.module_dimensions.y,
+            0.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{
+        testing::{self, named},
+        GenerationType, Sockets, Terrain, TerrainModule,
+    };
+
+    fn houses() -> Terrain {
+        testing::terrain(GenerationType::WaveCollapse, UVec2::new(4, 4), &[])
+            .with_module(TerrainModule {
+                size: UVec2::splat(2),
+                sockets: Sockets::all("house"),
+                ..named("house")
+            })
+            .with_module(TerrainModule {
+                sockets: Sockets::all("grass"),
+                ..named("grass")
+            })
+    }
+
+    fn module(terrain: &Terrain, name: &str) -> TerrainModule {
+        let id = testing::id(terrain, name);
+        terrain.layers[0].module(id).unwrap().clone()
+    }
+
+    #[test]
+    fn modules_never_leave_the_grid() {
+        let mut terrain = houses();
+        let house = module(&terrain, "house");
+        assert!(!terrain.insert(0, UVec2::new(3, 3), house.clone()));
+        assert!(!terrain.insert(0, UVec2::new(3, 0), house.clone()));
+        assert!(terrain.layers[0].map.is_empty());
+        assert!(terrain.insert(0, UVec2::new(2, 2), house));
+        assert_eq!(terrain.layers[0].map.len(), 4);
+    }
+
+    #[test]
+    fn modules_never_overlap() {
+        let mut terrain = houses();
+        let house = module(&terrain, "house");
+        let grass = module(&terrain, "grass");
+        assert!(terrain.insert(0, UVec2::ZERO, house.clone()));
+        assert!(!terrain.insert(0, UVec2::ONE, house));
+        assert!(!terrain.insert(0, UVec2::ONE, grass.clone()));
+        assert!(terrain.insert(0, UVec2::new(2, 2), grass));
+        assert_eq!(terrain.layers[0].anchor(UVec2::ONE), UVec2::ZERO);
+        assert_eq!(terrain.layers[0].count(testing::id(&terrain, "house")), 4);
+    }
+
+    #[test]
+    fn outer_edge_is_checked_when_editing() {
+        let mut terrain = houses();
+        let grass = module(&terrain, "grass");
+        // Only touches the house's footprint, not the cell it is placed at
+        terrain.insert(0, UVec2::new(2, 1), grass);
+        let house = testing::id(&terrain, "house");
+        assert!(!terrain.can_place("ground", UVec2::ZERO, house));
+        assert!(terrain.can_place("ground", UVec2::new(0, 2), house));
+    }
+}
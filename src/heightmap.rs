@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use noise::{Fbm, MultiFractal, NoiseFn, OpenSimplex, Perlin};
+
+use crate::{Terrain, TerrainModuleId, URect};
+
+/// Settings for [`GenerationType::Noise`](crate::GenerationType::Noise).
+///
+/// The sources are summed into a value between 0 and 1 for every cell, which picks the module of
+/// the first band whose `below` is higher than it.
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct NoiseSettings {
+    pub sources: Vec<NoiseSource>,
+    /// Ordered from low to high values, the last band also takes any value above it
+    pub bands: Vec<NoiseBand>,
+}
+
+impl NoiseSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_source(mut self, source: NoiseSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Cells with a value below `below`, and above the previous band, get the module with `id`
+    pub fn with_band(mut self, below: f64, id: TerrainModuleId) -> Self {
+        self.bands.push(NoiseBand { below, id });
+        self
+    }
+
+    fn band(&self, value: f64) -> Option<&NoiseBand> {
+        self.bands
+            .iter()
+            .find(|band| value < band.below)
+            .or_else(|| self.bands.last())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Inspectable)]
+pub struct NoiseSource {
+    pub kind: NoiseKind,
+    /// More than one octave makes it fractal brownian motion noise
+    pub octaves: usize,
+    /// How fast the noise changes from one cell to the next
+    pub frequency: f64,
+    /// How much the source counts compared to the others
+    pub amplitude: f64,
+    /// Added to the terrain's seed, so sources with the same settings can still differ
+    pub seed: u32,
+}
+
+impl Default for NoiseSource {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            octaves: 1,
+            frequency: 0.1,
+            amplitude: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+impl NoiseSource {
+    pub fn new(kind: NoiseKind, frequency: f64) -> Self {
+        Self {
+            kind,
+            frequency,
+            ..default()
+        }
+    }
+
+    pub fn fbm(kind: NoiseKind, octaves: usize, frequency: f64) -> Self {
+        Self {
+            kind,
+            octaves,
+            frequency,
+            ..default()
+        }
+    }
+
+    fn build(&self, seed: u32) -> Box<dyn NoiseFn<f64, 2>> {
+        match self.kind {
+            NoiseKind::Perlin => Box::new(
+                Fbm::<Perlin>::new(seed)
+                    .set_octaves(self.octaves)
+                    .set_frequency(self.frequency),
+            ),
+            NoiseKind::Simplex => Box::new(
+                Fbm::<OpenSimplex>::new(seed)
+                    .set_octaves(self.octaves)
+                    .set_frequency(self.frequency),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Inspectable)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+impl Default for NoiseKind {
+    fn default() -> Self {
+        NoiseKind::Perlin
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Inspectable)]
+pub struct NoiseBand {
+    pub below: f64,
+    pub id: TerrainModuleId,
+}
+
+impl Terrain {
+    /// Fills every empty cell of `layer` with the module of its noise band
+    pub(crate) fn generate_noise(&mut self, layer: usize, settings: &NoiseSettings) {
+        let noise = build(&settings.sources, self.seed);
+        for pos in URect::new(UVec2::ZERO, self.dimensions).positions() {
+            if self.layers[layer].map.contains_key(&pos) {
+                continue;
+            }
+            let id = match settings.band(sample(&settings.sources, &noise, pos)) {
+                Some(band) => band.id,
+                None => {
+                    warn!("No noise bands added to layer {}!", self.layers[layer].name);
+                    return;
+                }
+            };
+            self.place(layer, pos, id);
+        }
+    }
+}
+
+/// Builds the noise functions of `sources`, seeded from the terrain's seed
+pub(crate) fn build(sources: &[NoiseSource], seed: u64) -> Vec<Box<dyn NoiseFn<f64, 2>>> {
+    sources
+        .iter()
+        .map(|source| source.build((seed as u32).wrapping_add(source.seed)))
+        .collect()
+}
+
+/// Value between 0 and 1 of every source at `pos` added together
+pub(crate) fn sample(
+    sources: &[NoiseSource],
+    noise: &[Box<dyn NoiseFn<f64, 2>>],
+    pos: UVec2,
+) -> f64 {
+    let mut value = 0.0;
+    let mut amplitude = 0.0;
+    for (source, noise) in sources.iter().zip(noise.iter()) {
+        let sample = noise.get([pos.x as f64, pos.y as f64]);
+        value += (sample + 1.0) / 2.0 * source.amplitude;
+        amplitude += source.amplitude;
+    }
+    if amplitude > 0.0 {
+        (value / amplitude).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::{egui, Context, Inspectable, InspectableRegistry};
+
+use crate::{Terrain, TerrainModule};
+
+pub(crate) fn register(app: &mut App) {
+    app.register_type::<Terrain>()
+        .register_type::<TerrainModule>();
+    let mut registry = app
+        .world
+        .get_resource_or_insert_with(InspectableRegistry::default);
+    registry.register::<Terrain>();
+}
+
+impl Inspectable for Terrain {
+    type Attributes = ();
+
+    fn ui(&mut self, ui: &mut egui::Ui, _options: Self::Attributes, context: &mut Context) -> bool {
+        let mut changed = false;
+        ui.vertical_centered(|ui| {
+            egui::Grid::new(context.id()).show(ui, |ui| {
+                ui.label("dimensions");
+                changed |= self
+                    .dimensions
+                    .ui(ui, Default::default(), &mut context.with_id(0));
+                ui.end_row();
+                ui.label("module_dimensions");
+                changed |=
+                    self.module_dimensions
+                        .ui(ui, Default::default(), &mut context.with_id(1));
+                ui.end_row();
+                ui.label("seed");
+                changed |= self
+                    .seed
+                    .ui(ui, Default::default(), &mut context.with_id(2));
+                ui.end_row();
+                ui.label("state");
+                ui.label(self.state.name());
+                ui.end_row();
+                // Layers can't be added or removed here, rules and saves look them up by name
+                ui.label("layers");
+                ui.vertical(|ui| {
+                    for (index, layer) in self.layers.iter_mut().enumerate() {
+                        let mut layer_context = context.with_id(3 + index as u64);
+                        ui.collapsing(layer.name.clone(), |ui| {
+                            changed |=
+                                layer.ui(ui, Default::default(), &mut layer_context.with_id(0));
+                            // Modules are only added through the registry, which keeps their ids
+                            // and names unique
+                            for (index, module) in layer.modules.iter_mut().enumerate() {
+                                let label = if module.name.is_empty() {
+                                    format!("module {}", module.id)
+                                } else {
+                                    module.name.clone()
+                                };
+                                ui.collapsing(label, |ui| {
+                                    changed |= module.ui(
+                                        ui,
+                                        Default::default(),
+                                        &mut layer_context.with_id(1 + index as u64),
+                                    );
+                                });
+                            }
+                        });
+                    }
+                });
+                ui.end_row();
+            });
+            if ui.button("Regenerate").clicked() {
+                self.regenerate();
+                changed = true;
+            }
+        });
+        changed
+    }
+}
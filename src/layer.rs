@@ -0,0 +1,213 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_inspector_egui::Inspectable;
+
+use crate::{
+    footprint::footprint, Connectivity, DungeonLayout, GenerationType, ModuleLimit, PathConstraint,
+    PathStatus, Prefab, PrefabPlacement, Terrain, TerrainModule, TerrainModuleId,
+};
+
+/// One grid of modules on a [`Terrain`](crate::Terrain).
+///
+/// Layers are generated one after another, in the order they were added, and the rules of a layer
+/// can look at the layers below it through [`Adjacents::on_layer`](crate::Adjacents::on_layer).
+#[derive(Default, Inspectable)]
+pub struct TerrainLayer {
+    /// Shown as the layer's heading in the inspector instead, so it can't be renamed there
+    #[inspectable(ignore)]
+    pub(crate) name: String,
+    pub(crate) gen_type: GenerationType,
+    /// Z offset of the layer's sprites, so later layers can be drawn on top
+    pub(crate) z: f32,
+    /// Shown one by one in the inspector instead, so modules can't be added there
+    #[inspectable(ignore)]
+    pub(crate) modules: Vec<TerrainModule>,
+    /// How far rules can look, see [`Terrain::with_neighbourhood`](crate::Terrain::with_neighbourhood)
+    pub(crate) radius: u32,
+    /// Passable cells that have to be connected once the layer is generated
+    pub(crate) connectivity: Option<Connectivity>,
+    /// How often modules may be placed on the layer
+    pub(crate) limits: Vec<ModuleLimit>,
+    /// Paths that have to be walkable once the layer is generated
+    pub(crate) paths: Vec<PathConstraint>,
+    /// Patterns stamped onto the layer before wave collapse fills the rest
+    pub(crate) prefabs: Vec<Prefab>,
+    #[inspectable(ignore)]
+    pub(crate) map: HashMap<UVec2, TerrainModule>,
+    /// How many cells of `map` each module id takes up, kept up to date by `insert` and `remove`
+    #[inspectable(ignore)]
+    pub(crate) counts: HashMap<TerrainModuleId, usize>,
+    /// Where the multi-cell module covering a cell was placed
+    #[inspectable(ignore)]
+    pub(crate) anchors: HashMap<UVec2, UVec2>,
+    /// Biome of each cell, when generated with [`GenerationType::Biomes`]
+    #[inspectable(ignore)]
+    pub(crate) biomes: HashMap<UVec2, String>,
+    /// Rooms and corridors, when generated with [`GenerationType::Dungeon`]
+    #[inspectable(ignore)]
+    pub(crate) dungeon: Option<DungeonLayout>,
+    /// Sprites spawned for each cell once generation has finished
+    #[inspectable(ignore)]
+    pub(crate) tiles: HashMap<UVec2, Entity>,
+    /// Tiles of removed modules, despawned by the generation system
+    #[inspectable(ignore)]
+    pub(crate) despawn: Vec<Entity>,
+    /// Cells cleared to connect passable regions, only passable modules are allowed there
+    #[inspectable(ignore)]
+    pub(crate) required_passable: HashSet<UVec2>,
+    /// Repairs done since the layer's passable cells were last connected
+    #[inspectable(ignore)]
+    pub(crate) connect_attempts: u32,
+    /// Repairs done since the layer's limits were last met
+    #[inspectable(ignore)]
+    pub(crate) limit_attempts: u32,
+    /// Times the layer was generated again to route its paths with new ends
+    #[inspectable(ignore)]
+    pub(crate) path_attempts: u32,
+    /// Cells reserved for a path, with the modules allowed there
+    #[inspectable(ignore)]
+    pub(crate) path_cells: HashMap<UVec2, Vec<TerrainModuleId>>,
+    /// Whether each path could be walked, looked up once the layer is generated
+    #[inspectable(ignore)]
+    pub(crate) path_status: Vec<PathStatus>,
+    #[inspectable(ignore)]
+    pub(crate) placements: Vec<PrefabPlacement>,
+    /// Cells placed or removed since the terrain's [`NavGrid`](crate::NavGrid) was last updated
+    #[inspectable(ignore)]
+    pub(crate) nav_changed: HashSet<UVec2>,
+}
+
+impl TerrainLayer {
+    pub fn new(name: impl Into<String>, z: f32) -> Self {
+        Self {
+            name: name.into(),
+            z,
+            ..default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn gen_type(&self) -> &GenerationType {
+        &self.gen_type
+    }
+
+    pub fn z(&self) -> f32 {
+        self.z
+    }
+
+    pub fn radius(&self) -> u32 {
+        self.radius.max(1)
+    }
+
+    pub fn modules(&self) -> &[TerrainModule] {
+        &self.modules
+    }
+
+    pub fn module(&self, id: TerrainModuleId) -> Option<&TerrainModule> {
+        self.modules.iter().find(|module| module.id == id)
+    }
+
+    pub fn module_at(&self, pos: UVec2) -> Option<&TerrainModule> {
+        self.map.get(&pos)
+    }
+
+    pub fn connectivity(&self) -> Option<&Connectivity> {
+        self.connectivity.as_ref()
+    }
+
+    pub fn dungeon(&self) -> Option<&DungeonLayout> {
+        self.dungeon.as_ref()
+    }
+
+    pub fn limits(&self) -> &[ModuleLimit] {
+        &self.limits
+    }
+
+    pub fn paths(&self) -> &[PathConstraint] {
+        &self.paths
+    }
+
+    /// Whether the path at `index` in [`TerrainLayer::paths`] could be walked
+    pub fn path_status(&self, index: usize) -> &PathStatus {
+        self.path_status
+            .get(index)
+            .unwrap_or(&PathStatus::Unchecked)
+    }
+
+    /// Shortest walk of the path at `index` in [`TerrainLayer::paths`], if the layer is
+    /// generated and the path could be walked
+    pub fn found_path(&self, index: usize) -> Option<&[UVec2]> {
+        self.path_status(index).walk()
+    }
+
+    pub fn prefabs(&self) -> &[Prefab] {
+        &self.prefabs
+    }
+
+    /// Where copies of the layer's prefabs were stamped
+    pub fn placements(&self) -> &[PrefabPlacement] {
+        &self.placements
+    }
+
+    /// How many cells the modules with `id` cover
+    pub fn count(&self, id: TerrainModuleId) -> usize {
+        self.counts.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Places `module` at `pos`, covering its whole footprint if it is a multi-cell module.
+    /// Whatever covered those cells before is removed, go through `Terrain::insert` instead
+    /// to keep the footprint on the grid and off other modules.
+    pub(crate) fn insert(&mut self, pos: UVec2, module: TerrainModule) {
+        let area = footprint(pos, &module);
+        for cell in area.positions() {
+            self.remove(cell);
+            self.nav_changed.insert(cell);
+            if module.is_multi_cell() {
+                self.anchors.insert(cell, pos);
+                self.map.insert(cell, module.clone());
+            }
+        }
+        *self.counts.entry(module.id).or_default() += module.cells();
+        self.map.insert(pos, module);
+    }
+
+    /// Removes the module covering `pos`, along with the rest of its footprint. Its tile is
+    /// despawned by the generation system.
+    pub(crate) fn remove(&mut self, pos: UVec2) {
+        let anchor = self.anchor(pos);
+        if let Some(tile) = self.tiles.remove(&anchor) {
+            self.despawn.push(tile);
+        }
+        if let Some(old) = self.map.remove(&anchor) {
+            self.uncount(old.id, old.cells());
+            for cell in footprint(anchor, &old).positions() {
+                self.nav_changed.insert(cell);
+                self.anchors.remove(&cell);
+                self.map.remove(&cell);
+            }
+        }
+    }
+
+    fn uncount(&mut self, id: TerrainModuleId, cells: usize) {
+        if let Some(count) = self.counts.get_mut(&id) {
+            *count = count.saturating_sub(cells);
+        }
+    }
+
+    pub fn biome_at(&self, pos: UVec2) -> Option<&str> {
+        self.biomes.get(&pos).map(|biome| biome.as_str())
+    }
+}
+
+impl Terrain {
+    /// Layer the builders add to
+    pub(crate) fn last_layer_mut(&mut self) -> &mut TerrainLayer {
+        // Unwrap is fine because a terrain always has at least one layer
+        self.layers.last_mut().unwrap()
+    }
+}
@@ -1,14 +1,80 @@
+use std::{any::Any, sync::Arc};
+
 use bevy::{prelude::*, utils::HashMap};
-use rand::{prelude::SliceRandom, thread_rng, Rng};
+use bevy_inspector_egui::Inspectable;
+use rand::{prelude::SliceRandom, thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+mod autotile;
+mod biome;
+mod carve;
+mod cave;
+mod collision;
+mod connectivity;
+mod debug;
+mod dungeon;
+mod edit;
+mod footprint;
+mod heightmap;
+mod inspector;
+mod layer;
+mod limit;
+mod nav;
+mod path;
+mod prefab;
+mod regenerate;
+mod registry;
+mod rule;
+mod save;
+mod scatter;
+mod spawn;
+mod tag;
+#[cfg(test)]
+mod testing;
+
+pub use autotile::{AutotileMode, AutotileSettings, AutotileTile, TerrainType, TypeSource};
+pub use biome::{BiomeBand, BiomeSettings};
+pub use carve::Route;
+pub use cave::{CaveEdge, CaveSettings};
+pub use collision::{ColliderRect, TerrainColliders};
+pub use connectivity::Connectivity;
+pub use debug::TerrainDebug;
+pub use dungeon::{DungeonLayout, DungeonSettings};
+pub use edit::CellEdit;
+pub use heightmap::{NoiseBand, NoiseKind, NoiseSettings, NoiseSource};
+pub use layer::TerrainLayer;
+pub use limit::{Amount, ModuleLimit};
+pub use nav::{FlowField, NavGrid, TerrainNav};
+pub use path::{Endpoint, PathConstraint, PathStatus, Side};
+pub use prefab::{Prefab, PrefabPlacement};
+pub use regenerate::RegenerateTerrain;
+pub use registry::{ModuleError, TerrainModuleId};
+pub use rule::{GenerationRule, RuleContext};
+use save::SavedLayer;
+pub use save::{SaveError, SavedModule, TerrainSave};
+pub use scatter::{Density, Scatter, ScatteredObject};
+pub use spawn::{ModuleSpawner, SpawnContext};
+pub use tag::Sockets;
 
 pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(Terrain::generation);
+        app.add_event::<RegenerateTerrain>()
+            .add_system(Terrain::generation.label(TerrainSystem::Generation))
+            .add_system(RegenerateTerrain::handle.before(TerrainSystem::Generation))
+            .add_system(TerrainDebug::input.before(TerrainSystem::Generation))
+            .add_system(TerrainDebug::draw.after(TerrainSystem::Generation));
+        inspector::register(app);
     }
 }
 
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TerrainSystem {
+    Generation,
+}
+
 #[derive(Bundle, Default)]
 pub struct TerrainBundle {
     pub transform: Transform,
@@ -16,192 +82,621 @@ pub struct TerrainBundle {
     pub terrain: Terrain,
 }
 
-#[derive(Component)]
+#[derive(Component, Reflect)]
+#[reflect(Component)]
 pub struct Terrain {
-    gen_type: GenerationType,
     dimensions: UVec2,
     module_dimensions: Vec2,
-    map: HashMap<UVec2, TerrainModule>,
-    modules: Vec<TerrainModule>,
+    #[reflect(ignore)]
+    layers: Vec<TerrainLayer>,
+    /// Index of the layer being generated
+    #[reflect(ignore)]
+    layer: usize,
+    #[reflect(ignore)]
     state: GenerationState,
+    seed: u64,
+    #[reflect(ignore)]
+    rng: ChaCha12Rng,
+    #[reflect(ignore)]
+    stalemates: u32,
+    /// Handed to every rule, see [`Terrain::with_rule_data`]
+    #[reflect(ignore)]
+    rule_data: Option<Arc<dyn Any + Send + Sync>>,
+    /// Movement costs, updated once generation finishes
+    #[reflect(ignore)]
+    nav: NavGrid,
+    /// Objects spread over the terrain once every layer is generated
+    #[reflect(ignore)]
+    scatters: Vec<Scatter>,
+    /// `None` until every layer has been generated once
+    #[reflect(ignore)]
+    scattered: Option<Vec<ScatteredObject>>,
 }
 
 impl Default for Terrain {
     fn default() -> Self {
+        let seed = thread_rng().gen();
         Self {
-            gen_type: GenerationType::WaveCollapse,
             dimensions: Default::default(),
             module_dimensions: Default::default(),
-            map: Default::default(),
-            modules: Default::default(),
+            layers: vec![TerrainLayer::new("ground", 0.0)],
+            layer: 0,
             state: GenerationState::JustStarted,
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+            stalemates: 0,
+            rule_data: None,
+            nav: default(),
+            scatters: vec![],
+            scattered: None,
         }
     }
 }
 
 impl Terrain {
+    /// Creates a terrain with a single layer called "ground", generated with `gen_type`
     pub fn new(gen_type: GenerationType, dimensions: UVec2, module_dimensions: Vec2) -> Self {
         Terrain {
-            gen_type,
             dimensions,
             module_dimensions,
-            map: HashMap::new(),
-            modules: vec![],
-            state: GenerationState::JustStarted,
+            ..default()
         }
+        .with_generation_type(gen_type)
     }
 
+    /// Adds the module to the last added layer.
+    ///
+    /// # Panics
+    ///
+    /// If the layer already has a module with the same id or name, use
+    /// [`Terrain::add_module`] to handle that instead.
     pub fn with_module(mut self, module: TerrainModule) -> Terrain {
-        self.modules.push(module);
+        if let Err(err) = self.last_layer_mut().register(module) {
+            panic!("Couldn't add module: {}", err);
+        }
         self
     }
 
-    fn generation(
-        mut commands: Commands,
-        mut stalemate_amount: Local<u32>,
-        mut terrains: Query<(Entity, &mut Terrain)>,
-    ) {
-        for (entity, mut terrain) in terrains.iter_mut() {
-            match &terrain.state.clone() {
-                GenerationState::JustStarted => {
-                    *stalemate_amount = 0;
-                    if terrain.modules.is_empty() {
-                        warn!("No terrain modules added!");
-                        terrain.state = GenerationState::Finished;
-                    } else {
-                        // Unwrap is fine because we already checked if the vec is empty
-                        let mut rng = thread_rng();
-                        let module = terrain.modules.choose(&mut rng).unwrap().clone();
-                        let x = rng.gen_range(0..terrain.dimensions.x);
-                        let y = rng.gen_range(0..terrain.dimensions.y);
-                        let pos = UVec2::new(x, y);
-                        terrain.map.insert(pos, module);
-                        terrain.state = GenerationState::PlacedModules(vec![pos]);
+    /// Adds a layer on top of the others, modules added after it go into the new layer
+    pub fn with_layer(mut self, name: impl Into<String>, z: f32) -> Terrain {
+        self.layers.push(TerrainLayer::new(name, z));
+        self
+    }
+
+    /// Lets the rules of the last added layer look `radius` cells away through [`Adjacents::at`],
+    /// the solver checks that far around every placed cell
+    pub fn with_neighbourhood(mut self, radius: u32) -> Terrain {
+        self.last_layer_mut().radius = radius;
+        self
+    }
+
+    /// Sets how the last added layer is generated
+    pub fn with_generation_type(mut self, gen_type: GenerationType) -> Terrain {
+        self.last_layer_mut().gen_type = gen_type;
+        self
+    }
+
+    /// Makes generation reproducible, given the same modules and rules
+    pub fn with_seed(mut self, seed: u64) -> Terrain {
+        self.reseed(seed);
+        self
+    }
+
+    /// Generates everything from here on from `seed`, so regenerating gives a different map
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn dimensions(&self) -> UVec2 {
+        self.dimensions
+    }
+
+    pub fn layers(&self) -> &[TerrainLayer] {
+        &self.layers
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&TerrainLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    fn layer_index(&self, name: &str) -> Option<usize> {
+        let index = self.layers.iter().position(|layer| layer.name == name);
+        if index.is_none() {
+            warn!("Terrain has no layer called {}", name);
+        }
+        index
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, GenerationState::Done)
+    }
+
+    /// Throws away the generated map and starts over from the current seed and settings, which
+    /// gives the same map again unless they changed
+    pub fn regenerate(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.nav_changed.extend(layer.map.keys());
+            layer.map.clear();
+            layer.counts.clear();
+            layer.anchors.clear();
+            layer.biomes.clear();
+            layer.dungeon = None;
+            layer.required_passable.clear();
+            layer.connect_attempts = 0;
+            layer.limit_attempts = 0;
+            layer.path_attempts = 0;
+            layer.path_cells.clear();
+            layer.path_status.clear();
+            layer.placements.clear();
+            let tiles = layer.tiles.drain().map(|(_, tile)| tile);
+            layer.despawn.extend(tiles);
+        }
+        self.scattered = None;
+        self.layer = 0;
+        self.rng = ChaCha12Rng::seed_from_u64(self.seed);
+        self.state = GenerationState::JustStarted;
+    }
+
+    /// Modules of `layer` whose rule allows them at `pos`, given what is currently placed around it
+    pub fn allowed_modules(&self, layer: &str, pos: UVec2) -> Vec<TerrainModule> {
+        match self.layer_index(layer) {
+            Some(layer) => self.allowed(layer, pos),
+            None => vec![],
+        }
+    }
+
+    fn allowed(&self, layer: usize, pos: UVec2) -> Vec<TerrainModule> {
+        let context = self.rule_context(layer, pos, &self.layers[layer].map);
+        let biomes = self.biomes_around(layer, pos);
+        let passable = self.layers[layer].required_passable.contains(&pos);
+        let path = self.layers[layer].path_cells.get(&pos);
+        self.layers[layer]
+            .modules
+            .iter()
+            .filter(|module| biomes.is_empty() || module.fits_biomes(&biomes))
+            .filter(|module| !passable || module.passable)
+            .filter(|module| path.map_or(true, |through| path::walkable(through, module)))
+            .filter(|module| self.under_max(layer, module))
+            .filter(|module| self.footprint_fits(layer, pos, module))
+            .filter(|module| module.fits(&context))
+            .cloned()
+            .collect()
+    }
+
+    /// Neighbours of `pos` in `map`, along with what the other layers have at `pos`
+    fn adjacents(
+        &self,
+        layer: usize,
+        pos: UVec2,
+        map: &HashMap<UVec2, TerrainModule>,
+    ) -> Adjacents {
+        let mut adjacents = Adjacents::within(pos, map, self.layers[layer].radius());
+        for (index, other) in self.layers.iter().enumerate() {
+            if index == layer {
+                continue;
+            }
+            if let Some(module) = other.map.get(&pos) {
+                adjacents.layers.insert(other.name.clone(), module.clone());
+            }
+        }
+        adjacents
+    }
+
+    /// Cells whose rules can see `pos` on `layer`, which are also the ones it can see
+    fn neighbourhood(&self, layer: usize, pos: UVec2) -> Vec<UVec2> {
+        let radius = self.layers[layer].radius();
+        let min = UVec2::new(pos.x.saturating_sub(radius), pos.y.saturating_sub(radius));
+        let max = (pos + UVec2::splat(radius + 1)).min(self.dimensions);
+        URect::new(min, max)
+            .positions()
+            .filter(|other| *other != pos)
+            .collect()
+    }
+
+    /// Whether every cell of `layer` has a module
+    fn is_full(&self, layer: usize) -> bool {
+        let map = &self.layers[layer].map;
+        URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .all(|pos| map.contains_key(&pos))
+    }
+
+    /// Where the tile at `pos` on `layer` is placed relative to the terrain
+    fn tile_transform(&self, layer: usize, pos: UVec2) -> Transform {
+        Transform::from_xyz(
+            pos.x as f32 * self.module_dimensions.x,
+            -(pos.y as f32) * self.module_dimensions.y,
+            self.layers[layer].z,
+        )
+    }
+
+    /// Places prefabs and reserves paths before wave collapse starts, returns whether anything
+    /// was placed to solve around
+    fn prepare(&mut self, layer: usize) -> bool {
+        let stamped = self.stamp_prefabs(layer);
+        let routed = self.route_paths(layer);
+        if !stamped && !routed {
+            return false;
+        }
+        let empty: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .filter(|pos| !self.layers[layer].map.contains_key(pos))
+            .collect();
+        self.layer = layer;
+        self.solve_cleared(&empty);
+        true
+    }
+
+    /// Moves on to the next layer, or finishes once every layer is generated
+    fn next_layer(&mut self) {
+        self.stalemates = 0;
+        if self.repair_connectivity() || self.repair_limits() || self.repair_paths() {
+            return;
+        }
+        self.check_limits(self.layer);
+        self.check_paths(self.layer);
+        if self.layer + 1 >= self.layers.len() {
+            // Only a full generation scatters everything, edits and regenerated regions only
+            // rescatter the cells they changed once finished
+            if self.scattered.is_none() {
+                self.scatter_objects();
+            }
+            self.state = GenerationState::Finished;
+            return;
+        }
+        self.layer += 1;
+        let map = &self.layers[self.layer].map;
+        if map.is_empty() {
+            self.state = GenerationState::JustStarted;
+            return;
+        }
+        // Only part of the layer was cleared, by regenerating a region for example
+        let empty: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .filter(|pos| !map.contains_key(pos))
+            .collect();
+        if empty.is_empty() {
+            self.next_layer();
+        } else {
+            self.solve_cleared(&empty);
+        }
+    }
+
+    /// Advances generation by a single step, spawning is left to the generation system
+    fn step(&mut self) {
+        let layer = self.layer;
+        if self.state.is_generating() {
+            // Everything but wave collapse fills the whole layer at once
+            match self.layers[layer].gen_type.clone() {
+                GenerationType::WaveCollapse => {}
+                GenerationType::Noise(settings) => {
+                    self.generate_noise(layer, &settings);
+                    self.next_layer();
+                    return;
+                }
+                GenerationType::Biomes(settings) => {
+                    // Only once per layer, layers without biomes are wave collapsed without them
+                    let started = matches!(self.state, GenerationState::JustStarted);
+                    if started && self.layers[layer].biomes.is_empty() {
+                        self.generate_biomes(layer, &settings);
                     }
                 }
-                GenerationState::PlacedModules(modules) => {
-                    *stalemate_amount = 0;
-                    let mut inserted_positions = vec![];
-                    for pos in modules.iter() {
-                        let adjacents = get_adjacent_positions(pos);
-                        for adjacent in adjacents.iter() {
-                            if terrain.map.contains_key(adjacent)
-                                || adjacent.x > terrain.dimensions.x
-                                || adjacent.y > terrain.dimensions.y
-                            {
-                                continue;
-                            }
-                            let mut allowed_modules = vec![];
-                            for module in terrain.modules.iter() {
-                                let mut allowed = true;
-                                allowed = (module.generation_rule)(Adjacents::get(
-                                    *adjacent,
-                                    &terrain.map,
-                                )) && allowed;
-                                if allowed {
-                                    allowed_modules.push(module.clone());
-                                }
-                            }
-                            if allowed_modules.len() == 1 {
-                                terrain.map.insert(*adjacent, allowed_modules[0].clone());
-                                inserted_positions.push(*adjacent);
-                            }
+                GenerationType::CellularAutomata(settings) => {
+                    self.generate_cave(layer, &settings);
+                    self.next_layer();
+                    return;
+                }
+                GenerationType::Autotile(settings) => {
+                    self.generate_autotile(layer, &settings);
+                    self.next_layer();
+                    return;
+                }
+                GenerationType::Dungeon(settings) => {
+                    if self.layers[layer].dungeon.is_none() {
+                        if !self.generate_dungeon(layer, &settings) {
+                            self.next_layer();
                         }
-                    }
-                    if !inserted_positions.is_empty() {
-                        terrain.state = GenerationState::PlacedModules(inserted_positions);
-                    } else if terrain.map.len()
-                        == (terrain.dimensions.x * terrain.dimensions.y) as usize
-                    {
-                        terrain.state = GenerationState::Finished;
-                    } else {
-                        terrain.state = GenerationState::Stalemate;
+                        return;
                     }
                 }
-                GenerationState::Finished => {
-                    *stalemate_amount = 0;
-                    for (pos, module) in terrain.map.iter() {
-                        commands.entity(entity).with_children(|parent| {
-                            parent.spawn_bundle(SpriteBundle {
-                                transform: Transform::from_xyz(
-                                    pos.x as f32 * terrain.module_dimensions.x,
-                                    -(pos.y as f32) * terrain.module_dimensions.y,
-                                    0.0,
-                                ),
-                                texture: module.image.clone(),
-                                ..default()
-                            });
-                        });
+            }
+        }
+        match &self.state.clone() {
+            GenerationState::JustStarted => {
+                self.stalemates = 0;
+                if self.layers[layer].modules.is_empty() {
+                    warn!(
+                        "No terrain modules added to layer {}!",
+                        self.layers[layer].name
+                    );
+                    self.next_layer();
+                } else if self.prepare(layer) {
+                    // Solving goes on from the cells around what was placed up front
+                } else if let Some(pos) = self.place_missing(layer) {
+                    self.state = GenerationState::PlacedModules(vec![pos]);
+                } else {
+                    let x = self.rng.gen_range(0..self.dimensions.x);
+                    let y = self.rng.gen_range(0..self.dimensions.y);
+                    let pos = UVec2::new(x, y);
+                    let allowed_modules = self.allowed(layer, pos);
+                    match choose_module(&allowed_modules, &mut self.rng).cloned() {
+                        Some(module) if self.insert(layer, pos, module) => {
+                            self.state = GenerationState::PlacedModules(vec![pos]);
+                        }
+                        _ => self.state = GenerationState::Stalemate,
                     }
-                    commands.entity(entity).remove::<Terrain>();
                 }
-                GenerationState::Stalemate => {
-                    *stalemate_amount += 1;
-
-                    let mut rng = thread_rng();
-                    let x = rng.gen_range(0..terrain.dimensions.x);
-                    let y = rng.gen_range(0..terrain.dimensions.y);
-                    let mut pos = UVec2::new(x, y);
-                    while terrain.map.contains_key(&pos) {
-                        let x = rng.gen_range(0..terrain.dimensions.x);
-                        let y = rng.gen_range(0..terrain.dimensions.y);
-                        pos = UVec2::new(x, y);
-                    }
-                    let mut allowed_modules = vec![];
-                    for module in terrain.modules.iter() {
-                        if (module.generation_rule)(Adjacents::get(pos, &terrain.map)) {
-                            allowed_modules.push(module.clone());
+            }
+            GenerationState::PlacedModules(modules) => {
+                self.stalemates = 0;
+                let mut inserted_positions = vec![];
+                for pos in modules.iter() {
+                    for adjacent in self.neighbourhood(layer, *pos).iter() {
+                        if self.layers[layer].map.contains_key(adjacent) {
+                            continue;
+                        }
+                        let allowed_modules = self.allowed(layer, *adjacent);
+                        if allowed_modules.len() == 1
+                            && self.insert(layer, *adjacent, allowed_modules[0].clone())
+                        {
+                            inserted_positions.push(*adjacent);
                         }
                     }
-                    if let Some(module) = allowed_modules.choose(&mut rng).cloned() {
-                        terrain.map.insert(pos, module);
-                        terrain.state = GenerationState::PlacedModules(vec![pos]);
-                    } else if *stalemate_amount > 10 {
-                        warn!("Stalemated too much, aborting");
+                }
+                if !inserted_positions.is_empty() {
+                    self.state = GenerationState::PlacedModules(inserted_positions);
+                } else if self.is_full(layer) {
+                    self.next_layer();
+                } else {
+                    self.state = GenerationState::Stalemate;
+                }
+            }
+            GenerationState::Stalemate => {
+                self.stalemates += 1;
 
-                        terrain.state = GenerationState::Finished;
+                if let Some(pos) = self.place_missing(layer) {
+                    self.state = GenerationState::PlacedModules(vec![pos]);
+                    return;
+                }
+                let empty: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+                    .positions()
+                    .filter(|pos| !self.layers[layer].map.contains_key(pos))
+                    .collect();
+                let pos = match empty.choose(&mut self.rng) {
+                    Some(pos) => *pos,
+                    None => {
+                        self.next_layer();
+                        return;
                     }
+                };
+                let allowed_modules = self.allowed(layer, pos);
+                let module = choose_module(&allowed_modules, &mut self.rng).cloned();
+                if module.map_or(false, |module| self.insert(layer, pos, module)) {
+                    self.state = GenerationState::PlacedModules(vec![pos]);
+                } else if self.stalemates > 10 {
+                    warn!("Stalemated too much, aborting");
+
+                    self.next_layer();
+                }
+            }
+            GenerationState::Restoring {
+                layers,
+                layer,
+                resume,
+            } => {
+                self.restore(layers, *layer, resume);
+            }
+            GenerationState::Finished | GenerationState::Done => {}
+        }
+    }
+
+    fn generation(
+        mut commands: Commands,
+        mut terrains: Query<(Entity, &mut Terrain, Option<&mut TerrainDebug>)>,
+        mut scenes: Option<ResMut<SceneSpawner>>,
+    ) {
+        for (entity, mut terrain, debug) in terrains.iter_mut() {
+            if let Some(mut debug) = debug {
+                if debug.paused && !debug.step {
+                    continue;
                 }
+                debug.step = false;
+            }
+            let terrain = &mut *terrain;
+            for layer in terrain.layers.iter_mut() {
+                for tile in layer.despawn.drain(..) {
+                    commands.entity(tile).despawn_recursive();
+                }
+            }
+            match terrain.state {
+                GenerationState::Finished => {
+                    terrain.spawn_tiles(&mut commands, entity, &mut scenes);
+                    commands.entity(entity).insert(terrain.colliders());
+                    let changed = terrain.update_nav();
+                    terrain.rescatter(&changed);
+                    terrain.state = GenerationState::Done;
+                }
+                GenerationState::Done => {}
+                _ => terrain.step(),
             }
         }
     }
 }
 
-#[derive(Clone)]
+/// Rectangle of cells, `min` is inclusive and `max` exclusive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct URect {
+    pub min: UVec2,
+    pub max: UVec2,
+}
+
+impl URect {
+    pub fn new(min: UVec2, max: UVec2) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, pos: UVec2) -> bool {
+        pos.x >= self.min.x && pos.y >= self.min.y && pos.x < self.max.x && pos.y < self.max.y
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = UVec2> {
+        let URect { min, max } = *self;
+        (min.y..max.y).flat_map(move |y| (min.x..max.x).map(move |x| UVec2::new(x, y)))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 enum GenerationState {
     JustStarted,
     PlacedModules(Vec<UVec2>),
     Stalemate,
+    /// Spawns the tiles for every placed module
     Finished,
+    /// Tiles are spawned, nothing left to do
+    Done,
+    /// Fills the layers from saved module ids, then continues generating `layer` from `resume`
+    Restoring {
+        layers: Vec<SavedLayer>,
+        layer: usize,
+        resume: Box<GenerationState>,
+    },
+}
+
+impl GenerationState {
+    fn is_generating(&self) -> bool {
+        matches!(
+            self,
+            GenerationState::JustStarted
+                | GenerationState::PlacedModules(_)
+                | GenerationState::Stalemate
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            GenerationState::JustStarted => "Just started",
+            GenerationState::PlacedModules(_) => "Placed modules",
+            GenerationState::Stalemate => "Stalemate",
+            GenerationState::Finished => "Finished",
+            GenerationState::Done => "Done",
+            GenerationState::Restoring { .. } => "Restoring",
+        }
+    }
 }
 
+#[derive(Clone, Inspectable)]
 pub enum GenerationType {
     WaveCollapse,
+    /// Picks modules from bands of noise values, see [`NoiseSettings`]
+    Noise(NoiseSettings),
+    /// Assigns biomes to cells, then wave collapses with the modules of each biome, see
+    /// [`BiomeSettings`]
+    Biomes(BiomeSettings),
+    /// Grows caves out of random noise and picks wall modules to match, see [`CaveSettings`]
+    CellularAutomata(CaveSettings),
+    /// Splits the terrain into rooms connected by corridors, see [`DungeonSettings`]
+    Dungeon(DungeonSettings),
+    /// Picks transition tiles from a grid of terrain types, see [`AutotileSettings`]
+    Autotile(AutotileSettings),
+}
+
+impl Default for GenerationType {
+    fn default() -> Self {
+        GenerationType::WaveCollapse
+    }
+}
+
+impl GenerationType {
+    /// Whether cells of the layer are solved by wave collapse, so cleared cells can be solved
+    /// again to repair the layer
+    pub(crate) fn is_wave_collapse(&self) -> bool {
+        matches!(
+            self,
+            GenerationType::WaveCollapse | GenerationType::Biomes(_) | GenerationType::Dungeon(_)
+        )
+    }
 }
 
+/// What a [`TerrainModule::generation_rule`] gets to see around the cell it is checked for.
+///
+/// North is up, towards lower `y`. Beyond the eight cells around it, rules can look as far as the
+/// radius of their layer with [`Adjacents::at`], see [`Terrain::with_neighbourhood`].
+#[derive(Clone)]
 pub struct Adjacents {
     pub n: Option<TerrainModule>,
     pub s: Option<TerrainModule>,
     pub e: Option<TerrainModule>,
     pub w: Option<TerrainModule>,
+    pub ne: Option<TerrainModule>,
+    pub nw: Option<TerrainModule>,
+    pub se: Option<TerrainModule>,
+    pub sw: Option<TerrainModule>,
+    /// Modules at the same position on the terrain's other layers, by layer name
+    pub layers: HashMap<String, TerrainModule>,
+    /// Modules within the radius, by offset from the cell
+    neighbourhood: HashMap<IVec2, TerrainModule>,
 }
 
 impl Adjacents {
     pub fn get(pos: UVec2, map: &HashMap<UVec2, TerrainModule>) -> Self {
+        Self::within(pos, map, 1)
+    }
+
+    /// Gets every module up to `radius` cells away from `pos`, diagonally included
+    pub fn within(pos: UVec2, map: &HashMap<UVec2, TerrainModule>, radius: u32) -> Self {
+        let radius = radius.max(1) as i32;
+        let mut neighbourhood = HashMap::new();
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let offset = IVec2::new(x, y);
+                let other = pos.as_ivec2() + offset;
+                if offset == IVec2::ZERO || other.x < 0 || other.y < 0 {
+                    continue;
+                }
+                if let Some(module) = map.get(&other.as_uvec2()) {
+                    neighbourhood.insert(offset, module.clone());
+                }
+            }
+        }
+        let at = |x, y| neighbourhood.get(&IVec2::new(x, y)).cloned();
         Self {
-            n: (pos.y != 0)
-                .then(|| map.get(&UVec2::new(pos.x, pos.y - 1)))
-                .flatten()
-                .cloned(),
-            s: map.get(&UVec2::new(pos.x, pos.y + 1)).cloned(),
-            e: map.get(&UVec2::new(pos.x + 1, pos.y)).cloned(),
-            w: (pos.x != 0)
-                .then(|| map.get(&UVec2::new(pos.x - 1, pos.y)))
-                .flatten()
-                .cloned(),
+            n: at(0, -1),
+            s: at(0, 1),
+            e: at(1, 0),
+            w: at(-1, 0),
+            ne: at(1, -1),
+            nw: at(-1, -1),
+            se: at(1, 1),
+            sw: at(-1, 1),
+            layers: HashMap::new(),
+            neighbourhood,
         }
     }
 
+    /// The module `offset` cells away, as far as the radius of the layer
+    pub fn at(&self, offset: IVec2) -> Option<&TerrainModule> {
+        self.neighbourhood.get(&offset)
+    }
+
+    /// Every placed module at most `radius` cells away, with its offset
+    pub fn around(&self, radius: u32) -> impl Iterator<Item = (IVec2, &TerrainModule)> {
+        let radius = radius as i32;
+        self.neighbourhood
+            .iter()
+            .filter(move |(offset, _)| offset.x.abs() <= radius && offset.y.abs() <= radius)
+            .map(|(offset, module)| (*offset, module))
+    }
+
+    /// The module at the same position on another layer, if it has been generated already
+    pub fn on_layer(&self, name: &str) -> Option<&TerrainModule> {
+        self.layers.get(name)
+    }
+
     pub fn has_any(&self) -> bool {
         self.n.is_some() || self.s.is_some() || self.e.is_some() || self.w.is_some()
     }
@@ -221,13 +716,89 @@ impl Adjacents {
             self.w.as_ref(),
         ]
     }
+
+    pub fn diagonals(&self) -> Vec<Option<&TerrainModule>> {
+        vec![
+            self.ne.as_ref(),
+            self.nw.as_ref(),
+            self.se.as_ref(),
+            self.sw.as_ref(),
+        ]
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Inspectable, Reflect)]
 pub struct TerrainModule {
-    pub generation_rule: fn(adjacents: Adjacents) -> bool,
-    pub id: u32,
+    #[inspectable(ignore)]
+    #[reflect(ignore)]
+    pub generation_rule: GenerationRule,
+    /// Handed out when the module is added if left unassigned
+    #[inspectable(ignore)]
+    pub id: TerrainModuleId,
+    /// Unique within the layer if not empty, so the module can be referred to by name
+    #[inspectable(ignore)]
+    pub name: String,
+    /// Left at its default, no sprite is spawned for the module, e.g. for empty cells on a layer
     pub image: Handle<Image>,
+    /// Spawned as a child of the module's tile, if set
+    #[inspectable(ignore)]
+    pub scene: Handle<Scene>,
+    /// Adds components to the module's tile
+    #[inspectable(ignore)]
+    #[reflect(ignore)]
+    pub spawner: ModuleSpawner,
+    /// How likely this module is to be picked when several are allowed
+    pub weight: f32,
+    /// Biomes the module may be placed in, when its layer is generated with
+    /// [`GenerationType::Biomes`]. Modules without biomes fit anywhere.
+    pub biomes: Vec<String>,
+    /// Whether the module can be walked through, see [`Connectivity`]
+    pub passable: bool,
+    /// Whether the module blocks movement, see [`TerrainColliders`]
+    pub solid: bool,
+    /// Whether the module is water, for physics integrations to treat it as such
+    pub water: bool,
+    /// How expensive it is to walk through the module, relative to other passable modules
+    pub movement_cost: f32,
+    /// Cells the module covers, to the right of and below the cell it is placed at. Only wave
+    /// collapse checks that multi-cell modules fit, generators that pick modules by id don't.
+    pub size: UVec2,
+    /// Names for what the module is, for rules to match on instead of ids
+    pub tags: Vec<String>,
+    pub sockets: Sockets,
+}
+
+impl Default for TerrainModule {
+    fn default() -> Self {
+        Self {
+            generation_rule: default(),
+            id: default(),
+            name: String::new(),
+            image: Default::default(),
+            scene: Default::default(),
+            spawner: default(),
+            weight: 1.0,
+            biomes: vec![],
+            passable: false,
+            solid: false,
+            water: false,
+            movement_cost: 1.0,
+            size: UVec2::ONE,
+            tags: vec![],
+            sockets: default(),
+        }
+    }
+}
+
+/// Picks a module by weight, or uniformly if none of them has any weight
+fn choose_module<'a>(
+    modules: &'a [TerrainModule],
+    rng: &mut ChaCha12Rng,
+) -> Option<&'a TerrainModule> {
+    modules
+        .choose_weighted(&mut *rng, |module| module.weight)
+        .ok()
+        .or_else(|| modules.choose(rng))
 }
 
 /// Only checks on lower bounds of u32, because I mean c'mon
@@ -0,0 +1,275 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+
+use crate::{GenerationState, Terrain, TerrainModule, TerrainModuleId};
+
+/// How many times a missing module is tried at random empty cells before giving up for the step
+const MISSING_TRIES: usize = 20;
+
+/// How many times a generated layer is repaired to meet its limits before giving up
+const MAX_LIMIT_ATTEMPTS: u32 = 10;
+
+/// Number of cells on a layer, absolute or relative to the size of the terrain
+#[derive(Clone, Copy, Debug, Inspectable)]
+pub enum Amount {
+    Cells(usize),
+    /// Between 0 and 100
+    Percent(f32),
+}
+
+impl Default for Amount {
+    fn default() -> Self {
+        Amount::Cells(0)
+    }
+}
+
+impl Amount {
+    fn cells(&self, total: usize) -> usize {
+        match *self {
+            Amount::Cells(cells) => cells,
+            Amount::Percent(percent) => (total as f32 * percent / 100.0).round() as usize,
+        }
+    }
+}
+
+/// How often the module with `id` may be placed on a layer.
+///
+/// Amounts count the cells a module covers, so a 2x2 module counts as 4. Wave collapse never picks
+/// a module past its `max`, and places modules short of their `min` whenever it is free to
+/// choose. Once the layer is generated, modules still short of their `min` replace random cells
+/// that allow them, and limits that still aren't met after that are reported with a warning.
+#[derive(Clone, Copy, Debug, Default, Inspectable)]
+pub struct ModuleLimit {
+    pub id: TerrainModuleId,
+    pub min: Option<Amount>,
+    pub max: Option<Amount>,
+}
+
+impl ModuleLimit {
+    pub fn at_least(id: TerrainModuleId, min: Amount) -> Self {
+        Self {
+            id,
+            min: Some(min),
+            max: None,
+        }
+    }
+
+    pub fn at_most(id: TerrainModuleId, max: Amount) -> Self {
+        Self {
+            id,
+            min: None,
+            max: Some(max),
+        }
+    }
+
+    pub fn exactly(id: TerrainModuleId, amount: Amount) -> Self {
+        Self::between(id, amount, amount)
+    }
+
+    pub fn between(id: TerrainModuleId, min: Amount, max: Amount) -> Self {
+        Self {
+            id,
+            min: Some(min),
+            max: Some(max),
+        }
+    }
+}
+
+impl Terrain {
+    /// Limits how often a module of the last added layer is placed
+    pub fn with_limit(mut self, limit: ModuleLimit) -> Terrain {
+        self.last_layer_mut().limits.push(limit);
+        self
+    }
+
+    fn cell_count(&self) -> usize {
+        (self.dimensions.x * self.dimensions.y) as usize
+    }
+
+    /// Whether another `module` can be placed on `layer` without going past its max
+    pub(crate) fn under_max(&self, layer: usize, module: &TerrainModule) -> bool {
+        let layer = &self.layers[layer];
+        let count = layer.count(module.id) + module.cells();
+        layer
+            .limits
+            .iter()
+            .filter(|limit| limit.id == module.id)
+            .filter_map(|limit| limit.max)
+            .all(|max| count <= max.cells(self.cell_count()))
+    }
+
+    /// Modules of `layer` that are short of their min
+    fn missing(&self, layer: usize) -> Vec<TerrainModuleId> {
+        let total = self.cell_count();
+        self.layers[layer]
+            .limits
+            .iter()
+            .filter(|limit| {
+                limit.min.map_or(false, |min| {
+                    self.layers[layer].count(limit.id) < min.cells(total)
+                })
+            })
+            .map(|limit| limit.id)
+            .collect()
+    }
+
+    /// Places a module that is short of its min at a random empty cell of `layer` that allows it
+    pub(crate) fn place_missing(&mut self, layer: usize) -> Option<UVec2> {
+        for id in self.missing(layer) {
+            for _ in 0..MISSING_TRIES {
+                let x = self.rng.gen_range(0..self.dimensions.x);
+                let y = self.rng.gen_range(0..self.dimensions.y);
+                let pos = UVec2::new(x, y);
+                if self.layers[layer].map.contains_key(&pos) {
+                    continue;
+                }
+                if let Some(module) = self
+                    .allowed(layer, pos)
+                    .into_iter()
+                    .find(|module| module.id == id)
+                {
+                    if self.insert(layer, pos, module) {
+                        return Some(pos);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Replaces a random cell of the current layer with a module short of its min, clearing the
+    /// neighbours that don't fit it to be solved again. Returns whether anything was replaced.
+    pub(crate) fn repair_limits(&mut self) -> bool {
+        let layer = self.layer;
+        let missing = self.missing(layer);
+        if missing.is_empty()
+            || !self.layers[layer].gen_type.is_wave_collapse()
+            || self.layers[layer].limit_attempts >= MAX_LIMIT_ATTEMPTS
+        {
+            self.layers[layer].limit_attempts = 0;
+            return false;
+        }
+        self.layers[layer].limit_attempts += 1;
+        // Cells of modules with a min of their own are left alone, so they don't fall short
+        let has_min = |id: TerrainModuleId| {
+            self.layers[layer]
+                .limits
+                .iter()
+                .any(|limit| limit.id == id && limit.min.is_some())
+        };
+        for id in missing {
+            let module = match self.layers[layer].module(id) {
+                Some(module) => module.clone(),
+                None => continue,
+            };
+            for _ in 0..MISSING_TRIES {
+                let x = self.rng.gen_range(0..self.dimensions.x);
+                let y = self.rng.gen_range(0..self.dimensions.y);
+                let pos = UVec2::new(x, y);
+                let replaced = self.layers[layer].module_at(pos).map(|old| old.id);
+                if replaced.map_or(false, has_min)
+                    || !self.under_max(layer, &module)
+                    || !self.footprint_free(layer, pos, &module, true)
+                {
+                    continue;
+                }
+                let misfits = self.misfits(layer, pos, &module);
+                if misfits.contains(&pos) {
+                    continue;
+                }
+                self.clear(layer, pos);
+                self.insert(layer, pos, module);
+                if misfits.is_empty() {
+                    self.state = GenerationState::PlacedModules(vec![pos]);
+                } else {
+                    for misfit in misfits.iter() {
+                        self.clear(layer, *misfit);
+                    }
+                    self.solve_cleared(&misfits);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Warns about every limit of `layer` that isn't met
+    pub(crate) fn check_limits(&self, layer: usize) {
+        let total = self.cell_count();
+        let layer = &self.layers[layer];
+        for limit in layer.limits.iter() {
+            let count = layer.count(limit.id);
+            if let Some(min) = limit.min.filter(|min| count < min.cells(total)) {
+                warn!(
+                    "Layer {} has {} of module {}, needs at least {}",
+                    layer.name,
+                    count,
+                    limit.id,
+                    min.cells(total)
+                );
+            }
+            if let Some(max) = limit.max.filter(|max| count > max.cells(total)) {
+                warn!(
+                    "Layer {} has {} of module {}, allows at most {}",
+                    layer.name,
+                    count,
+                    limit.id,
+                    max.cells(total)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, finish, named},
+        GenerationType, URect,
+    };
+
+    #[test]
+    fn percents_round_to_the_nearest_cell() {
+        assert_eq!(Amount::Percent(12.5).cells(10), 1);
+        assert_eq!(Amount::Percent(15.0).cells(10), 2);
+        assert_eq!(Amount::Percent(100.0).cells(64), 64);
+        assert_eq!(Amount::Cells(3).cells(64), 3);
+    }
+
+    #[test]
+    fn multi_cell_modules_count_every_cell_against_their_max() {
+        let mut terrain = testing::terrain(GenerationType::WaveCollapse, UVec2::new(4, 4), &[])
+            .with_module(TerrainModule {
+                size: UVec2::splat(2),
+                ..named("house")
+            });
+        let id = testing::id(&terrain, "house");
+        terrain = terrain.with_limit(ModuleLimit::at_most(id, Amount::Cells(6)));
+        let house = terrain.layers[0].module(id).unwrap().clone();
+        assert!(terrain.under_max(0, &house));
+        terrain.insert(0, UVec2::ZERO, house.clone());
+        assert!(!terrain.under_max(0, &house));
+    }
+
+    #[test]
+    fn repairs_replace_cells_until_the_min_is_met() {
+        let mut terrain = testing::terrain(
+            GenerationType::WaveCollapse,
+            UVec2::new(8, 8),
+            &["grass", "sand"],
+        )
+        .with_seed(7);
+        let grass = testing::id(&terrain, "grass");
+        let sand = testing::id(&terrain, "sand");
+        terrain = terrain.with_limit(ModuleLimit::at_least(sand, Amount::Cells(3)));
+        for pos in URect::new(UVec2::ZERO, terrain.dimensions()).positions() {
+            terrain.place(0, pos, grass);
+        }
+        terrain.state = GenerationState::Stalemate;
+        finish(&mut terrain);
+        assert!(terrain.layers[0].count(sand) >= 3);
+        assert_eq!(terrain.layers[0].limit_attempts, 0);
+    }
+}
@@ -0,0 +1,321 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+
+use crate::{get_adjacent_positions, Terrain, URect};
+
+/// Movement costs of every cell of a generated terrain, for finding paths over it.
+///
+/// A cell can be walked through when any module on it is
+/// [`passable`](crate::TerrainModule::passable) and none is
+/// [`solid`](crate::TerrainModule::solid). Entering it costs the highest
+/// [`movement_cost`](crate::TerrainModule::movement_cost) of its modules. The grid is kept up to
+/// date as cells are edited, only the changed cells are looked at again.
+#[derive(Clone, Debug, Default)]
+pub struct NavGrid {
+    dimensions: UVec2,
+    /// Cost of entering each cell row by row, `None` for cells that can't be walked through
+    costs: Vec<Option<f32>>,
+}
+
+impl NavGrid {
+    pub fn dimensions(&self) -> UVec2 {
+        self.dimensions
+    }
+
+    pub fn cost(&self, pos: UVec2) -> Option<f32> {
+        self.index(pos).and_then(|index| self.costs[index])
+    }
+
+    pub fn is_walkable(&self, pos: UVec2) -> bool {
+        self.cost(pos).is_some()
+    }
+
+    /// Cheapest walk from `from` to `to` including both ends, found with A*
+    pub fn path(&self, from: UVec2, to: UVec2) -> Option<Vec<UVec2>> {
+        if !self.is_walkable(from) || !self.is_walkable(to) {
+            return None;
+        }
+        // Every step costs at least the cheapest cell, which keeps the estimate from overshooting
+        let cheapest = self.costs.iter().flatten().fold(f32::MAX, |a, b| a.min(*b));
+        a_star(from, to, cheapest, |next| self.cost(next))
+    }
+
+    /// Cost of the cheapest walk from every cell to the closest of `goals`, found with Dijkstra.
+    /// Cells that can't reach a goal are `None`.
+    pub fn distances(&self, goals: &[UVec2]) -> Vec<Option<f32>> {
+        let mut distances = vec![None; self.costs.len()];
+        let mut open = BinaryHeap::new();
+        for goal in goals.iter().filter(|goal| self.is_walkable(**goal)) {
+            // Unwrap is fine because walkable cells are in bounds
+            distances[self.index(*goal).unwrap()] = Some(0.0);
+            open.push(Reverse((0, goal.x, goal.y)));
+        }
+        while let Some(Reverse((_, x, y))) = open.pop() {
+            let pos = UVec2::new(x, y);
+            let distance = distances[self.index(pos).unwrap()].unwrap_or(0.0);
+            // Walking from `next` to `pos` costs entering `pos`
+            let step_cost = self.cost(pos).unwrap_or(0.0);
+            for (next, _) in self.neighbours(pos) {
+                let index = self.index(next).unwrap();
+                let next_distance = distance + step_cost;
+                if distances[index].map_or(true, |known| next_distance < known) {
+                    distances[index] = Some(next_distance);
+                    open.push(Reverse((hundredths(next_distance), next.x, next.y)));
+                }
+            }
+        }
+        distances
+    }
+
+    /// Direction to step in from every cell to get closer to the closest of `goals`, for moving
+    /// many agents to the same place without a path for each of them
+    pub fn flow_field(&self, goals: &[UVec2]) -> FlowField {
+        let distances = self.distances(goals);
+        let distance = |pos: UVec2| self.index(pos).and_then(|index| distances[index]);
+        let directions = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .map(|pos| {
+                let own = distance(pos)?;
+                let (next, next_distance) = self
+                    .neighbours(pos)
+                    .into_iter()
+                    .filter_map(|(next, _)| Some((next, distance(next)?)))
+                    .min_by_key(|(_, distance)| hundredths(*distance))?;
+                (next_distance < own).then(|| next.as_ivec2() - pos.as_ivec2())
+            })
+            .collect();
+        FlowField {
+            dimensions: self.dimensions,
+            directions,
+        }
+    }
+
+    /// Walkable cells next to `pos`, along with what entering them costs
+    fn neighbours(&self, pos: UVec2) -> Vec<(UVec2, f32)> {
+        get_adjacent_positions(&pos)
+            .into_iter()
+            .filter_map(|next| Some((next, self.cost(next)?)))
+            .collect()
+    }
+
+    fn index(&self, pos: UVec2) -> Option<usize> {
+        (pos.x < self.dimensions.x && pos.y < self.dimensions.y)
+            .then(|| (pos.y * self.dimensions.x + pos.x) as usize)
+    }
+}
+
+/// Costs are ordered in hundredths, since floats can't be
+fn hundredths(cost: f32) -> u32 {
+    (cost.max(0.0) * 100.0) as u32
+}
+
+/// Cheapest walk from `from` to `to` including both ends, found with A*. `step_cost` is the cost
+/// of entering a cell, `None` for cells that can't be entered, and no step may cost less than
+/// `cheapest`.
+pub(crate) fn a_star(
+    from: UVec2,
+    to: UVec2,
+    cheapest: f32,
+    step_cost: impl Fn(UVec2) -> Option<f32>,
+) -> Option<Vec<UVec2>> {
+    let estimate = |pos: UVec2| {
+        let distance = pos.as_ivec2() - to.as_ivec2();
+        (distance.x.unsigned_abs() + distance.y.unsigned_abs()) as f32 * cheapest.max(0.0)
+    };
+    let mut came_from = HashMap::default();
+    let mut costs = HashMap::default();
+    let mut open = BinaryHeap::new();
+    costs.insert(from, 0.0);
+    open.push(Reverse((hundredths(estimate(from)), from.x, from.y)));
+    while let Some(Reverse((_, x, y))) = open.pop() {
+        let pos = UVec2::new(x, y);
+        if pos == to {
+            let mut route = vec![pos];
+            let mut current = pos;
+            while let Some(previous) = came_from.get(&current) {
+                current = *previous;
+                route.push(current);
+            }
+            route.reverse();
+            return Some(route);
+        }
+        for next in get_adjacent_positions(&pos) {
+            let step_cost = match step_cost(next) {
+                Some(step_cost) => step_cost,
+                None => continue,
+            };
+            let next_cost = costs[&pos] + step_cost;
+            if costs.get(&next).map_or(true, |known| next_cost < *known) {
+                costs.insert(next, next_cost);
+                came_from.insert(next, pos);
+                open.push(Reverse((
+                    hundredths(next_cost + estimate(next)),
+                    next.x,
+                    next.y,
+                )));
+            }
+        }
+    }
+    None
+}
+
+/// Directions towards a goal for every cell, see [`NavGrid::flow_field`]
+#[derive(Clone, Debug, Default)]
+pub struct FlowField {
+    dimensions: UVec2,
+    directions: Vec<Option<IVec2>>,
+}
+
+impl FlowField {
+    /// One cell step towards the goal, `None` at the goal and for cells that can't reach it
+    pub fn direction(&self, pos: UVec2) -> Option<IVec2> {
+        if pos.x >= self.dimensions.x || pos.y >= self.dimensions.y {
+            return None;
+        }
+        self.directions[(pos.y * self.dimensions.x + pos.x) as usize]
+    }
+}
+
+/// Path queries over the terrains of the world, by their entity
+#[derive(SystemParam)]
+pub struct TerrainNav<'w, 's> {
+    terrains: Query<'w, 's, &'static Terrain>,
+}
+
+impl<'w, 's> TerrainNav<'w, 's> {
+    /// Cheapest walk from `from` to `to` on the terrain of `entity`, see [`NavGrid::path`]
+    pub fn terrain_path(&self, entity: Entity, from: UVec2, to: UVec2) -> Option<Vec<UVec2>> {
+        self.grid(entity)?.path(from, to)
+    }
+
+    pub fn flow_field(&self, entity: Entity, goals: &[UVec2]) -> Option<FlowField> {
+        Some(self.grid(entity)?.flow_field(goals))
+    }
+
+    /// Navigation grid of the terrain of `entity`, once it is generated
+    pub fn grid(&self, entity: Entity) -> Option<&NavGrid> {
+        let terrain = self.terrains.get(entity).ok()?;
+        terrain.is_finished().then(|| terrain.nav_grid())
+    }
+}
+
+impl Terrain {
+    pub fn nav_grid(&self) -> &NavGrid {
+        &self.nav
+    }
+
+    /// Looks at the cells changed since the last update again, or at every cell if the grid
+    /// doesn't match the terrain. Returns the cells looked at.
+    pub(crate) fn update_nav(&mut self) -> Vec<UVec2> {
+        let changed: Vec<UVec2> = if self.nav.dimensions != self.dimensions {
+            for layer in self.layers.iter_mut() {
+                layer.nav_changed.clear();
+            }
+            self.nav = NavGrid {
+                dimensions: self.dimensions,
+                costs: vec![None; (self.dimensions.x * self.dimensions.y) as usize],
+            };
+            URect::new(UVec2::ZERO, self.dimensions)
+                .positions()
+                .collect()
+        } else {
+            self.layers
+                .iter_mut()
+                .flat_map(|layer| layer.nav_changed.drain())
+                .collect()
+        };
+        for pos in changed.iter().copied() {
+            let index = match self.nav.index(pos) {
+                Some(index) => index,
+                None => continue,
+            };
+            let modules = self.layers.iter().filter_map(|layer| layer.map.get(&pos));
+            let mut passable = false;
+            let mut cost: Option<f32> = Some(0.0);
+            for module in modules {
+                passable |= module.passable;
+                if module.solid {
+                    cost = None;
+                }
+                cost = cost.map(|cost| cost.max(module.movement_cost));
+            }
+            self.nav.costs[index] = cost.filter(|_| passable);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid from rows of `.` for cells costing 1, digits for other costs and `#` for walls
+    fn grid(rows: &[&str]) -> NavGrid {
+        NavGrid {
+            dimensions: UVec2::new(rows[0].len() as u32, rows.len() as u32),
+            costs: rows
+                .iter()
+                .flat_map(|row| row.chars())
+                .map(|cell| match cell {
+                    '#' => None,
+                    '.' => Some(1.0),
+                    cost => Some(cost.to_digit(10).unwrap() as f32),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn path_goes_around_walls() {
+        let grid = grid(&[".#.", ".#.", "..."]);
+        let path = grid.path(UVec2::new(0, 0), UVec2::new(2, 0)).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                UVec2::new(0, 0),
+                UVec2::new(0, 1),
+                UVec2::new(0, 2),
+                UVec2::new(1, 2),
+                UVec2::new(2, 2),
+                UVec2::new(2, 1),
+                UVec2::new(2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn path_avoids_costly_cells() {
+        let grid = grid(&[".5.", "..."]);
+        let path = grid.path(UVec2::new(0, 0), UVec2::new(2, 0)).unwrap();
+        assert_eq!(
+            path,
+            vec![
+                UVec2::new(0, 0),
+                UVec2::new(0, 1),
+                UVec2::new(1, 1),
+                UVec2::new(2, 1),
+                UVec2::new(2, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_path_to_walled_off_cells() {
+        let grid = grid(&[".#.", ".#."]);
+        assert_eq!(grid.path(UVec2::new(0, 0), UVec2::new(2, 0)), None);
+        assert_eq!(grid.path(UVec2::new(0, 0), UVec2::new(1, 0)), None);
+    }
+
+    #[test]
+    fn flow_field_points_towards_goal() {
+        let grid = grid(&[".#.", ".#.", "..."]);
+        let field = grid.flow_field(&[UVec2::new(2, 0)]);
+        assert_eq!(field.direction(UVec2::new(0, 0)), Some(IVec2::new(0, 1)));
+        assert_eq!(field.direction(UVec2::new(0, 2)), Some(IVec2::new(1, 0)));
+        assert_eq!(field.direction(UVec2::new(2, 2)), Some(IVec2::new(0, -1)));
+        assert_eq!(field.direction(UVec2::new(2, 1)), Some(IVec2::new(0, -1)));
+        assert_eq!(field.direction(UVec2::new(2, 0)), None);
+        assert_eq!(field.direction(UVec2::new(1, 0)), None);
+    }
+}
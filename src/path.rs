@@ -0,0 +1,389 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+
+use crate::{
+    get_adjacent_positions, GenerationState, GenerationType, Route, Terrain, TerrainModule,
+    TerrainModuleId, URect,
+};
+
+/// How many random cells are tried for an end of a path that is far enough from the other end
+const ENDPOINT_TRIES: usize = 100;
+
+/// How many times a layer is generated again with new path ends before a short path is accepted
+const MAX_PATH_ATTEMPTS: u32 = 5;
+
+/// A walkable path that has to exist between two ends on a layer.
+///
+/// Before wave collapse starts, the ends are picked and a route between them is reserved for the
+/// `through` modules, so the solver only has to pick which of them fit. Once the layer is
+/// generated the shortest walk between the ends is looked up again. A walk shorter than
+/// `min_distance` has the layer generated again with new ends a few times, and a warning is given
+/// if it is still missing or too short after that. The outcome can be read from
+/// [`TerrainLayer::path_status`](crate::TerrainLayer::path_status).
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct PathConstraint {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    /// Modules the path can go through, [`TerrainModule::passable`] modules when empty
+    pub through: Vec<TerrainModuleId>,
+    /// Fewest cells walked from one end to the other. Ends are picked at least this many cells
+    /// apart counting only straight steps, which no walk between them can beat.
+    pub min_distance: u32,
+    /// Shape of the reserved route
+    pub route: Route,
+}
+
+impl PathConstraint {
+    pub fn new(from: Endpoint, to: Endpoint) -> Self {
+        Self {
+            from,
+            to,
+            ..default()
+        }
+    }
+
+    pub fn through(mut self, ids: impl IntoIterator<Item = TerrainModuleId>) -> Self {
+        self.through.extend(ids);
+        self
+    }
+
+    pub fn min_distance(mut self, min_distance: u32) -> Self {
+        self.min_distance = min_distance;
+        self
+    }
+
+    pub fn routed(mut self, route: Route) -> Self {
+        self.route = route;
+        self
+    }
+
+    fn walkable(&self, module: &TerrainModule) -> bool {
+        walkable(&self.through, module)
+    }
+}
+
+/// Outcome of looking up a [`PathConstraint`] on its generated layer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathStatus {
+    /// The layer hasn't been generated since the terrain was set up, restored or regenerated
+    Unchecked,
+    /// Shortest walk from one end to the other, including both ends
+    Found(Vec<UVec2>),
+    /// Shortest walk, which takes fewer steps than `min_distance`
+    TooShort(Vec<UVec2>),
+    /// The ends aren't connected
+    Missing,
+}
+
+impl Default for PathStatus {
+    fn default() -> Self {
+        PathStatus::Unchecked
+    }
+}
+
+impl PathStatus {
+    pub fn walk(&self) -> Option<&[UVec2]> {
+        match self {
+            PathStatus::Found(walk) | PathStatus::TooShort(walk) => Some(walk),
+            PathStatus::Unchecked | PathStatus::Missing => None,
+        }
+    }
+
+    pub fn is_found(&self) -> bool {
+        matches!(self, PathStatus::Found(_))
+    }
+}
+
+/// One end of a [`PathConstraint`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Inspectable)]
+pub enum Endpoint {
+    Cell(UVec2),
+    /// Any cell along a side of the terrain
+    Edge(Side),
+    /// Wherever the module with this id is, it gets placed at a random cell if needed
+    Module(TerrainModuleId),
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Endpoint::Cell(UVec2::ZERO)
+    }
+}
+
+/// Side of a terrain, north being the top row
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Inspectable)]
+pub enum Side {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Default for Side {
+    fn default() -> Self {
+        Side::North
+    }
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::North, Side::East, Side::South, Side::West];
+
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::North => Side::South,
+            Side::East => Side::West,
+            Side::South => Side::North,
+            Side::West => Side::East,
+        }
+    }
+
+    /// Cells along this side of a terrain of `dimensions`
+    pub fn cells(&self, dimensions: UVec2) -> URect {
+        match self {
+            Side::North => URect::new(UVec2::ZERO, UVec2::new(dimensions.x, 1)),
+            Side::East => URect::new(UVec2::new(dimensions.x - 1, 0), dimensions),
+            Side::South => URect::new(UVec2::new(0, dimensions.y - 1), dimensions),
+            Side::West => URect::new(UVec2::ZERO, UVec2::new(1, dimensions.y)),
+        }
+    }
+}
+
+/// Whether `module` can be walked on by a path going `through` these ids
+pub(crate) fn walkable(through: &[TerrainModuleId], module: &TerrainModule) -> bool {
+    if through.is_empty() {
+        module.passable
+    } else {
+        through.contains(&module.id)
+    }
+}
+
+impl Terrain {
+    /// Requires a path on the last added layer
+    pub fn with_path(mut self, path: PathConstraint) -> Terrain {
+        self.last_layer_mut().paths.push(path);
+        self
+    }
+
+    /// Picks the ends of every path on `layer` and reserves a route between them, returns whether
+    /// any path was routed
+    pub(crate) fn route_paths(&mut self, layer: usize) -> bool {
+        let terrain_layer = &self.layers[layer];
+        if terrain_layer.paths.is_empty() || !terrain_layer.path_cells.is_empty() {
+            return false;
+        }
+        for path in terrain_layer.paths.clone() {
+            let from = self.pick_endpoint(path.from, None, 0);
+            let to =
+                from.and_then(|from| self.pick_endpoint(path.to, Some(from), path.min_distance));
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) => (from, to),
+                _ => {
+                    warn!(
+                        "Couldn't fit a path from {:?} to {:?} on layer {}",
+                        path.from, path.to, self.layers[layer].name
+                    );
+                    continue;
+                }
+            };
+            for pos in self.carve(from, to, &path.route) {
+                match (path.from, path.to) {
+                    (Endpoint::Module(id), _) if pos == from => {
+                        self.place(layer, pos, id);
+                    }
+                    (_, Endpoint::Module(id)) if pos == to => {
+                        self.place(layer, pos, id);
+                    }
+                    _ => {
+                        let through = path.through.clone();
+                        self.layers[layer].path_cells.insert(pos, through);
+                    }
+                }
+            }
+        }
+        !self.layers[layer].path_cells.is_empty()
+    }
+
+    /// Cell for `endpoint`, at least `min_distance` straight steps away from `other`
+    fn pick_endpoint(
+        &mut self,
+        endpoint: Endpoint,
+        other: Option<UVec2>,
+        min_distance: u32,
+    ) -> Option<UVec2> {
+        let far_enough = |pos: UVec2| {
+            other.map_or(true, |other| {
+                let distance = pos.as_ivec2() - other.as_ivec2();
+                distance.x.unsigned_abs() + distance.y.unsigned_abs() >= min_distance
+            })
+        };
+        let area = match endpoint {
+            Endpoint::Cell(pos) => {
+                let fits = pos.x < self.dimensions.x && pos.y < self.dimensions.y;
+                return (fits && far_enough(pos)).then(|| pos);
+            }
+            Endpoint::Edge(side) => side.cells(self.dimensions),
+            Endpoint::Module(_) => URect::new(UVec2::ZERO, self.dimensions),
+        };
+        (0..ENDPOINT_TRIES)
+            .map(|_| {
+                let x = self.rng.gen_range(area.min.x..area.max.x);
+                let y = self.rng.gen_range(area.min.y..area.max.y);
+                UVec2::new(x, y)
+            })
+            .find(|pos| far_enough(*pos))
+    }
+
+    /// Clears the current layer to be generated again with new path ends when a path is shorter
+    /// than its `min_distance`, returns whether it was cleared
+    pub(crate) fn repair_paths(&mut self) -> bool {
+        let layer = self.layer;
+        let terrain_layer = &self.layers[layer];
+        let too_short = terrain_layer.paths.iter().any(|path| {
+            self.find_path(layer, path)
+                .map_or(false, |walk| (walk.len() as u32) <= path.min_distance)
+        });
+        // Only layers solved from scratch route their paths again when cleared
+        let rerouted = matches!(
+            terrain_layer.gen_type,
+            GenerationType::WaveCollapse | GenerationType::Biomes(_)
+        );
+        if !too_short || !rerouted || terrain_layer.path_attempts >= MAX_PATH_ATTEMPTS {
+            self.layers[layer].path_attempts = 0;
+            return false;
+        }
+        self.layers[layer].path_attempts += 1;
+        for pos in URect::new(UVec2::ZERO, self.dimensions).positions() {
+            self.clear(layer, pos);
+        }
+        self.layers[layer].path_cells.clear();
+        self.layers[layer].placements.clear();
+        self.stalemates = 0;
+        self.state = GenerationState::JustStarted;
+        true
+    }
+
+    /// Looks up the shortest walk of every path on `layer`, warning about paths that fail
+    pub(crate) fn check_paths(&mut self, layer: usize) {
+        let terrain_layer = &self.layers[layer];
+        let status = terrain_layer
+            .paths
+            .iter()
+            .map(|path| match self.find_path(layer, path) {
+                None => {
+                    warn!(
+                        "No path from {:?} to {:?} on layer {}",
+                        path.from, path.to, terrain_layer.name
+                    );
+                    PathStatus::Missing
+                }
+                Some(walk) if (walk.len() as u32) <= path.min_distance => {
+                    warn!(
+                        "Path from {:?} to {:?} on layer {} is {} cells, needs at least {}",
+                        path.from,
+                        path.to,
+                        terrain_layer.name,
+                        walk.len() - 1,
+                        path.min_distance
+                    );
+                    PathStatus::TooShort(walk)
+                }
+                Some(walk) => PathStatus::Found(walk),
+            })
+            .collect();
+        self.layers[layer].path_status = status;
+    }
+
+    /// Shortest walk between the ends of `path` on `layer`
+    fn find_path(&self, layer: usize, path: &PathConstraint) -> Option<Vec<UVec2>> {
+        let map = &self.layers[layer].map;
+        let from = self.endpoint_cells(layer, path, path.from);
+        let to: HashSet<UVec2> = self
+            .endpoint_cells(layer, path, path.to)
+            .into_iter()
+            .collect();
+        let mut came_from = HashMap::default();
+        let mut open = VecDeque::new();
+        for pos in from.iter() {
+            came_from.insert(*pos, *pos);
+            open.push_back(*pos);
+        }
+        while let Some(pos) = open.pop_front() {
+            if to.contains(&pos) {
+                let mut walk = vec![pos];
+                let mut current = pos;
+                while came_from[&current] != current {
+                    current = came_from[&current];
+                    walk.push(current);
+                }
+                walk.reverse();
+                return Some(walk);
+            }
+            for adjacent in get_adjacent_positions(&pos) {
+                let walkable = to.contains(&adjacent)
+                    || map
+                        .get(&adjacent)
+                        .map_or(false, |module| path.walkable(module));
+                if walkable && !came_from.contains_key(&adjacent) {
+                    came_from.insert(adjacent, pos);
+                    open.push_back(adjacent);
+                }
+            }
+        }
+        None
+    }
+
+    /// Cells `endpoint` can be at in grid order, edges only count where `path` can walk
+    fn endpoint_cells(
+        &self,
+        layer: usize,
+        path: &PathConstraint,
+        endpoint: Endpoint,
+    ) -> Vec<UVec2> {
+        let map = &self.layers[layer].map;
+        match endpoint {
+            Endpoint::Cell(pos) => [pos].into_iter().collect(),
+            Endpoint::Edge(side) => side
+                .cells(self.dimensions)
+                .positions()
+                .filter(|pos| map.get(pos).map_or(false, |module| path.walkable(module)))
+                .collect(),
+            Endpoint::Module(id) => URect::new(UVec2::ZERO, self.dimensions)
+                .positions()
+                .filter(|pos| map.get(pos).map_or(false, |module| module.id == id))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, finish, named};
+
+    #[test]
+    fn short_paths_are_retried_a_few_times() {
+        let mut terrain = testing::terrain(GenerationType::WaveCollapse, UVec2::new(8, 8), &[])
+            .with_module(TerrainModule {
+                passable: true,
+                ..named("floor")
+            })
+            // No two cells of opposite edges are this far apart
+            .with_path(
+                PathConstraint::new(Endpoint::Edge(Side::West), Endpoint::Edge(Side::East))
+                    .min_distance(20),
+            )
+            .with_seed(2);
+        finish(&mut terrain);
+        assert!(matches!(
+            terrain.layers[0].path_status(0),
+            PathStatus::TooShort(_)
+        ));
+        assert_eq!(terrain.layers[0].path_attempts, 0);
+    }
+}
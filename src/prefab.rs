@@ -0,0 +1,288 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{Terrain, TerrainModuleId, URect};
+
+/// How many random spots are tried for each copy of a prefab
+const PLACEMENT_TRIES: usize = 50;
+
+/// A hand-made pattern of modules, stamped onto a layer before wave collapse fills the rest.
+///
+/// Copies go to random spots where none of their cells are taken yet, at least `spacing` cells
+/// away from every other prefab on the layer. The pattern is trusted to fit together, only the
+/// cells around it are solved to fit the pattern. Copies are only turned when every module of
+/// the pattern has turned counterparts, see [`Prefab::rotated`].
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct Prefab {
+    /// Width of the pattern in cells
+    pub width: u32,
+    /// Module ids row by row, `None` cells are left for wave collapse to fill
+    pub cells: Vec<Option<TerrainModuleId>>,
+    /// How many copies are placed
+    pub count: usize,
+    /// Fewest free cells between this prefab and any other
+    pub spacing: u32,
+    /// Each module of the pattern turned clockwise 0, 1, 2 and 3 quarter turns
+    #[inspectable(ignore)]
+    pub rotations: Vec<[TerrainModuleId; 4]>,
+}
+
+impl Prefab {
+    /// A single copy of the pattern, `rows` going from top to bottom
+    pub fn new(rows: &[&[Option<TerrainModuleId>]]) -> Self {
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        Self {
+            width: width as u32,
+            // Short rows are padded with cells left for wave collapse
+            cells: rows
+                .iter()
+                .flat_map(|row| {
+                    let padding = std::iter::repeat(None).take(width - row.len());
+                    row.iter().copied().chain(padding)
+                })
+                .collect(),
+            count: 1,
+            ..default()
+        }
+    }
+
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = count;
+        self
+    }
+
+    pub fn with_spacing(mut self, spacing: u32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Lets copies be turned by quarter turns. Each of `rotations` is a module followed by the
+    /// modules that look like it turned clockwise once, twice and three times, symmetric modules
+    /// can repeat themselves.
+    pub fn rotated(mut self, rotations: impl IntoIterator<Item = [TerrainModuleId; 4]>) -> Self {
+        self.rotations.extend(rotations);
+        self
+    }
+
+    /// `id` turned clockwise `turns` times, if it has turned counterparts
+    fn turned_id(&self, id: TerrainModuleId, turns: u8) -> Option<TerrainModuleId> {
+        let rotation = self
+            .rotations
+            .iter()
+            .find(|rotation| rotation.contains(&id))?;
+        // Unwrap is fine because `rotation` contains `id`
+        let own = rotation.iter().position(|other| *other == id).unwrap();
+        Some(rotation[(own + turns as usize) % 4])
+    }
+
+    fn size(&self) -> UVec2 {
+        if self.width == 0 {
+            return UVec2::ZERO;
+        }
+        UVec2::new(self.width, self.cells.len() as u32 / self.width)
+    }
+
+    /// Placed cells of the pattern, relative to its top left corner
+    fn placed_cells(&self) -> Vec<(UVec2, TerrainModuleId)> {
+        URect::new(UVec2::ZERO, self.size())
+            .positions()
+            .zip(self.cells.iter())
+            .filter_map(|(pos, id)| id.map(|id| (pos, id)))
+            .collect()
+    }
+
+    /// Size and placed cells of the pattern turned clockwise `turns` times, with every module
+    /// swapped for its turned counterpart. `None` if a module has no counterpart.
+    fn turned(&self, turns: u8) -> Option<(UVec2, Vec<(UVec2, TerrainModuleId)>)> {
+        let size = self.size();
+        let turned_size = if turns % 2 == 0 {
+            size
+        } else {
+            UVec2::new(size.y, size.x)
+        };
+        let cells = self
+            .placed_cells()
+            .into_iter()
+            .map(|(pos, id)| {
+                let turned = match turns % 4 {
+                    0 => pos,
+                    1 => UVec2::new(size.y - 1 - pos.y, pos.x),
+                    2 => UVec2::new(size.x - 1 - pos.x, size.y - 1 - pos.y),
+                    _ => UVec2::new(pos.y, size.x - 1 - pos.x),
+                };
+                let id = if turns % 4 == 0 {
+                    id
+                } else {
+                    self.turned_id(id, turns)?
+                };
+                Some((turned, id))
+            })
+            .collect::<Option<_>>()?;
+        Some((turned_size, cells))
+    }
+}
+
+/// Where a copy of a prefab was stamped
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefabPlacement {
+    /// Index into [`TerrainLayer::prefabs`](crate::TerrainLayer::prefabs)
+    pub prefab: usize,
+    pub area: URect,
+    /// Clockwise quarter turns
+    #[serde(default)]
+    pub turns: u8,
+}
+
+impl Terrain {
+    /// Stamps copies of `prefab` onto the last added layer before it is generated
+    pub fn with_prefab(mut self, prefab: Prefab) -> Terrain {
+        self.last_layer_mut().prefabs.push(prefab);
+        self
+    }
+
+    /// Stamps every prefab of `layer`, returns whether any copy was placed
+    pub(crate) fn stamp_prefabs(&mut self, layer: usize) -> bool {
+        if !self.layers[layer].placements.is_empty() {
+            return false;
+        }
+        for (index, prefab) in self.layers[layer].prefabs.clone().iter().enumerate() {
+            let rotate = !prefab.rotations.is_empty() && self.can_rotate(layer, index, prefab);
+            let mut placed = 0;
+            for _ in 0..prefab.count * PLACEMENT_TRIES {
+                if placed == prefab.count {
+                    break;
+                }
+                let turns = if rotate { self.rng.gen_range(0..4) } else { 0 };
+                // Unwrap is fine because copies are only turned when every module has counterparts
+                let (size, cells) = prefab.turned(turns).unwrap();
+                if size.x == 0 || size.x > self.dimensions.x || size.y > self.dimensions.y {
+                    break;
+                }
+                let x = self.rng.gen_range(0..=self.dimensions.x - size.x);
+                let y = self.rng.gen_range(0..=self.dimensions.y - size.y);
+                let area = URect::new(UVec2::new(x, y), UVec2::new(x, y) + size);
+                if !self.prefab_fits(layer, area, prefab.spacing) {
+                    continue;
+                }
+                for (pos, id) in cells.iter() {
+                    self.place(layer, area.min + *pos, *id);
+                }
+                self.layers[layer].placements.push(PrefabPlacement {
+                    prefab: index,
+                    area,
+                    turns,
+                });
+                placed += 1;
+            }
+            if placed < prefab.count {
+                warn!(
+                    "Only placed {} of {} copies of prefab {} on layer {}",
+                    placed, prefab.count, index, self.layers[layer].name
+                );
+            }
+        }
+        !self.layers[layer].placements.is_empty()
+    }
+
+    /// Whether every module of `prefab` has single cell counterparts to turn into, warns if not
+    fn can_rotate(&self, layer: usize, index: usize, prefab: &Prefab) -> bool {
+        let terrain_layer = &self.layers[layer];
+        let single_cell = |id: &TerrainModuleId| {
+            terrain_layer
+                .module(*id)
+                .map_or(false, |module| !module.is_multi_cell())
+        };
+        let turnable =
+            prefab.turned(1).is_some() && prefab.rotations.iter().flatten().all(single_cell);
+        if !turnable {
+            warn!(
+                "Prefab {} on layer {} has modules without single cell counterparts, its copies \
+                 aren't turned",
+                index, terrain_layer.name
+            );
+        }
+        turnable
+    }
+
+    /// Whether `area` is free and at least `spacing` cells away from the other prefabs
+    fn prefab_fits(&self, layer: usize, area: URect, spacing: u32) -> bool {
+        let terrain_layer = &self.layers[layer];
+        let spaced = URect::new(
+            UVec2::new(
+                area.min.x.saturating_sub(spacing),
+                area.min.y.saturating_sub(spacing),
+            ),
+            area.max + UVec2::splat(spacing),
+        );
+        area.positions()
+            .all(|pos| !terrain_layer.map.contains_key(&pos))
+            && terrain_layer.placements.iter().all(|placement| {
+                let other = placement.area;
+                other.max.x <= spaced.min.x
+                    || other.max.y <= spaced.min.y
+                    || other.min.x >= spaced.max.x
+                    || other.min.y >= spaced.max.y
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, GenerationType};
+
+    fn ids(first: u32) -> [TerrainModuleId; 4] {
+        [0, 1, 2, 3].map(|turns| TerrainModuleId(first + turns))
+    }
+
+    #[test]
+    fn turning_swaps_cells_and_modules() {
+        let (a, b) = (ids(0), ids(4));
+        let prefab = Prefab::new(&[&[Some(a[0]), Some(b[0])]]).rotated([a, b]);
+        assert_eq!(
+            prefab.turned(1),
+            Some((
+                UVec2::new(1, 2),
+                vec![(UVec2::new(0, 0), a[1]), (UVec2::new(0, 1), b[1])]
+            ))
+        );
+        assert_eq!(
+            prefab.turned(2),
+            Some((
+                UVec2::new(2, 1),
+                vec![(UVec2::new(1, 0), a[2]), (UVec2::new(0, 0), b[2])]
+            ))
+        );
+        // Starting from a turned module goes on from its own turn
+        let turned = Prefab::new(&[&[Some(a[3])]]).rotated([a]);
+        assert_eq!(
+            turned.turned(1),
+            Some((UVec2::ONE, vec![(UVec2::ZERO, a[0])]))
+        );
+    }
+
+    #[test]
+    fn modules_without_counterparts_are_not_turned() {
+        let a = ids(0);
+        let prefab = Prefab::new(&[&[Some(a[0]), Some(TerrainModuleId(9))]]).rotated([a]);
+        assert_eq!(prefab.turned(1), None);
+        assert!(prefab.turned(0).is_some());
+
+        let mut terrain = testing::terrain(
+            GenerationType::WaveCollapse,
+            UVec2::splat(12),
+            &["a", "b", "c", "d", "e"],
+        );
+        let prefab = Prefab::new(&[&[Some(TerrainModuleId(0)), Some(TerrainModuleId(4))]])
+            .with_count(4)
+            .rotated([ids(0)]);
+        terrain = terrain.with_prefab(prefab);
+        assert!(terrain.stamp_prefabs(0));
+        for placement in terrain.layers[0].placements() {
+            assert_eq!(placement.turns, 0);
+            assert_eq!(placement.area.max - placement.area.min, UVec2::new(2, 1));
+        }
+    }
+}
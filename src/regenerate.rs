@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use rand::{thread_rng, Rng};
+
+use crate::{GenerationState, GenerationType, Terrain, URect};
+
+/// Send to throw away part of a terrain and generate it again.
+///
+/// Only the cells inside `region` are cleared, or the whole map if it is `None`. The new cells are
+/// generated to fit the cells around them, and their sprites replace the old ones once done.
+pub struct RegenerateTerrain {
+    pub entity: Entity,
+    pub region: Option<URect>,
+    /// Seed of the new cells, a random one when `None` so every event rolls a new map
+    pub seed: Option<u64>,
+}
+
+impl RegenerateTerrain {
+    pub(crate) fn handle(
+        mut events: EventReader<RegenerateTerrain>,
+        mut terrains: Query<&mut Terrain>,
+    ) {
+        for event in events.iter() {
+            let mut terrain = match terrains.get_mut(event.entity) {
+                Ok(terrain) => terrain,
+                Err(_) => {
+                    warn!("Can't regenerate {:?}, it has no terrain", event.entity);
+                    continue;
+                }
+            };
+            terrain.reseed(event.seed.unwrap_or_else(|| thread_rng().gen()));
+            match event.region {
+                Some(region) => terrain.regenerate_region(region),
+                None => terrain.regenerate(),
+            }
+        }
+    }
+}
+
+impl Terrain {
+    /// Clears the cells inside `region` on every layer and generates them again, keeping the rest
+    /// of the map. Noise only changes with the seed, see [`Terrain::reseed`].
+    pub fn regenerate_region(&mut self, region: URect) {
+        let region = URect::new(
+            region.min.min(self.dimensions),
+            region.max.min(self.dimensions),
+        );
+        let cleared: Vec<UVec2> = region.positions().collect();
+        if cleared.is_empty() {
+            return;
+        }
+        for layer in 0..self.layers.len() {
+            for pos in cleared.iter() {
+                self.clear(layer, *pos);
+            }
+            // Dungeons keep their layout, only the floor of rooms is solved again if at all
+            let dungeon = self.layers[layer].dungeon.clone();
+            if let (GenerationType::Dungeon(settings), Some(layout)) =
+                (self.layers[layer].gen_type.clone(), dungeon)
+            {
+                self.fill_dungeon(layer, &settings, &layout);
+            }
+        }
+        self.layer = 0;
+        self.solve_cleared(&cleared);
+    }
+
+    /// Removes the module at `pos` on `layer`, its sprite is despawned by the generation system.
+    /// Multi-cell modules are removed as a whole.
+    pub(crate) fn clear(&mut self, layer: usize, pos: UVec2) {
+        self.layers[layer].remove(pos);
+    }
+
+    /// Generates the `cleared` cells of the current layer again, starting from the placed cells
+    /// around them so the new cells are solved to fit. Layers above it are generated afterwards.
+    pub(crate) fn solve_cleared(&mut self, cleared: &[UVec2]) {
+        let map = &self.layers[self.layer].map;
+        // Kept in order, so the same seed solves the cells in the same order
+        let mut border = vec![];
+        for pos in cleared.iter() {
+            for adjacent in self.neighbourhood(self.layer, *pos) {
+                if map.contains_key(&adjacent) && !border.contains(&adjacent) {
+                    border.push(adjacent);
+                }
+            }
+        }
+        self.stalemates = 0;
+        self.state = if border.is_empty() {
+            GenerationState::Stalemate
+        } else {
+            GenerationState::PlacedModules(border)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, finish},
+        DungeonSettings,
+    };
+
+    fn cells(terrain: &Terrain) -> Vec<Option<String>> {
+        URect::new(UVec2::ZERO, terrain.dimensions())
+            .positions()
+            .map(|pos| testing::name_at(terrain, pos).map(|name| name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn new_seed_rolls_a_new_map() {
+        let mut terrain = testing::terrain(
+            GenerationType::WaveCollapse,
+            UVec2::new(8, 8),
+            &["grass", "sand", "water"],
+        )
+        .with_seed(1);
+        finish(&mut terrain);
+        let first = cells(&terrain);
+        terrain.regenerate();
+        finish(&mut terrain);
+        assert_eq!(cells(&terrain), first);
+        terrain.reseed(2);
+        terrain.regenerate();
+        finish(&mut terrain);
+        assert_ne!(cells(&terrain), first);
+    }
+
+    #[test]
+    fn dungeon_regions_keep_their_layout() {
+        let mut terrain = testing::terrain(
+            GenerationType::Dungeon(default()),
+            UVec2::splat(32),
+            &["floor", "wall", "door", "void"],
+        )
+        .with_seed(4);
+        let id = |name| testing::id(&terrain, name);
+        let settings = DungeonSettings::new(id("floor"), id("wall"), id("door"), id("void"));
+        terrain = terrain.with_generation_type(GenerationType::Dungeon(settings));
+        finish(&mut terrain);
+        let first = cells(&terrain);
+        terrain.reseed(5);
+        terrain.regenerate_region(URect::new(UVec2::new(4, 4), UVec2::new(28, 28)));
+        finish(&mut terrain);
+        assert_eq!(cells(&terrain), first);
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{egui, Context, Inspectable};
+use serde::{Deserialize, Serialize};
+
+use crate::{Terrain, TerrainLayer, TerrainModule};
+
+/// Identifies a module within its layer.
+///
+/// Modules left at [`TerrainModuleId::UNASSIGNED`] get the lowest free id of their layer when
+/// they are added, which can be looked up by name with [`TerrainLayer::id_of`].
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Reflect,
+)]
+pub struct TerrainModuleId(pub u32);
+
+impl TerrainModuleId {
+    pub const UNASSIGNED: TerrainModuleId = TerrainModuleId(u32::MAX);
+}
+
+impl Default for TerrainModuleId {
+    fn default() -> Self {
+        Self::UNASSIGNED
+    }
+}
+
+impl From<u32> for TerrainModuleId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl Inspectable for TerrainModuleId {
+    type Attributes = ();
+
+    fn ui(&mut self, ui: &mut egui::Ui, _options: Self::Attributes, context: &mut Context) -> bool {
+        self.0.ui(ui, Default::default(), context)
+    }
+}
+
+impl fmt::Display for TerrainModuleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Why a module couldn't be added to a layer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModuleError {
+    DuplicateId { layer: String, id: TerrainModuleId },
+    DuplicateName { layer: String, name: String },
+    NoLayer(String),
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleError::DuplicateId { layer, id } => {
+                write!(f, "Layer {} already has a module with id {}", layer, id)
+            }
+            ModuleError::DuplicateName { layer, name } => {
+                write!(f, "Layer {} already has a module called {}", layer, name)
+            }
+            ModuleError::NoLayer(layer) => write!(f, "Terrain has no layer called {}", layer),
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+impl TerrainLayer {
+    /// Adds `module`, handing it an id if it has none. Nothing is added when the id or name is
+    /// already taken.
+    pub(crate) fn register(
+        &mut self,
+        mut module: TerrainModule,
+    ) -> Result<TerrainModuleId, ModuleError> {
+        if module.id == TerrainModuleId::UNASSIGNED {
+            // Lowest id that isn't taken yet
+            let mut ids: Vec<u32> = self.modules.iter().map(|module| module.id.0).collect();
+            ids.sort_unstable();
+            let free = ids
+                .iter()
+                .enumerate()
+                .find(|(index, id)| *index as u32 != **id)
+                .map_or(ids.len() as u32, |(index, _)| index as u32);
+            module.id = TerrainModuleId(free);
+        } else if self.module(module.id).is_some() {
+            return Err(ModuleError::DuplicateId {
+                layer: self.name.clone(),
+                id: module.id,
+            });
+        }
+        if !module.name.is_empty() && self.id_of(&module.name).is_some() {
+            return Err(ModuleError::DuplicateName {
+                layer: self.name.clone(),
+                name: module.name,
+            });
+        }
+        let id = module.id;
+        self.modules.push(module);
+        Ok(id)
+    }
+
+    pub fn module_named(&self, name: &str) -> Option<&TerrainModule> {
+        self.modules.iter().find(|module| module.name == name)
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<TerrainModuleId> {
+        self.module_named(name).map(|module| module.id)
+    }
+}
+
+impl Terrain {
+    /// Adds `module` to `layer` after generation has been set up, returns the id it got or why
+    /// it was left out
+    pub fn add_module(
+        &mut self,
+        layer: &str,
+        module: TerrainModule,
+    ) -> Result<TerrainModuleId, ModuleError> {
+        self.layers
+            .iter_mut()
+            .find(|other| other.name == layer)
+            .ok_or_else(|| ModuleError::NoLayer(layer.to_string()))?
+            .register(module)
+    }
+
+    /// Id of the module called `name` on `layer`
+    pub fn module_id(&self, layer: &str, name: &str) -> Option<TerrainModuleId> {
+        self.layer(layer)?.id_of(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, named},
+        GenerationType,
+    };
+
+    fn terrain() -> Terrain {
+        testing::terrain(GenerationType::WaveCollapse, UVec2::splat(4), &[])
+    }
+
+    #[test]
+    fn hands_out_lowest_free_ids() {
+        let mut terrain = terrain();
+        let taken = TerrainModule {
+            id: TerrainModuleId(1),
+            ..named("taken")
+        };
+        assert_eq!(terrain.add_module("ground", taken), Ok(TerrainModuleId(1)));
+        assert_eq!(
+            terrain.add_module("ground", named("a")),
+            Ok(TerrainModuleId(0))
+        );
+        assert_eq!(
+            terrain.add_module("ground", named("b")),
+            Ok(TerrainModuleId(2))
+        );
+        assert_eq!(terrain.module_id("ground", "b"), Some(TerrainModuleId(2)));
+        assert_eq!(terrain.module_id("ground", "c"), None);
+    }
+
+    #[test]
+    fn duplicates_are_left_out() {
+        let mut terrain = terrain().with_module(named("a"));
+        let same_id = TerrainModule {
+            id: TerrainModuleId(0),
+            ..named("b")
+        };
+        assert_eq!(
+            terrain.add_module("ground", same_id),
+            Err(ModuleError::DuplicateId {
+                layer: "ground".to_string(),
+                id: TerrainModuleId(0),
+            })
+        );
+        assert_eq!(
+            terrain.add_module("ground", named("a")),
+            Err(ModuleError::DuplicateName {
+                layer: "ground".to_string(),
+                name: "a".to_string(),
+            })
+        );
+        assert_eq!(
+            terrain.add_module("water", named("c")),
+            Err(ModuleError::NoLayer("water".to_string()))
+        );
+        assert_eq!(terrain.layer("ground").unwrap().modules().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already has a module called a")]
+    fn with_module_panics_on_duplicates() {
+        terrain().with_module(named("a")).with_module(named("a"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::{any::Any, sync::Arc};
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{Adjacents, Terrain, TerrainModule};
+
+/// Decides whether a module may be placed, see [`TerrainModule::generation_rule`].
+///
+/// Wraps any closure, so rules can capture settings or be built from config at runtime.
+#[derive(Clone)]
+pub struct GenerationRule(Arc<dyn Fn(&RuleContext) -> bool + Send + Sync>);
+
+impl GenerationRule {
+    pub fn new(rule: impl Fn(&RuleContext) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(rule))
+    }
+
+    pub fn allows(&self, context: &RuleContext) -> bool {
+        (self.0)(context)
+    }
+}
+
+/// Allows the module anywhere
+impl Default for GenerationRule {
+    fn default() -> Self {
+        Self::new(|_| true)
+    }
+}
+
+/// Everything a [`GenerationRule`] knows about the cell it is checked for
+pub struct RuleContext<'a> {
+    pub pos: UVec2,
+    /// Size of the terrain in cells
+    pub dimensions: UVec2,
+    /// Name of the layer the module would be placed on
+    pub layer: &'a str,
+    pub adjacents: Adjacents,
+    data: Option<&'a (dyn Any + Send + Sync)>,
+}
+
+impl<'a> RuleContext<'a> {
+    /// Data given to [`Terrain::with_rule_data`], if it is a `T`
+    pub fn data<T: Any>(&self) -> Option<&'a T> {
+        self.data?.downcast_ref()
+    }
+}
+
+impl Terrain {
+    /// Hands `data` to every rule through [`RuleContext::data`]
+    pub fn with_rule_data(mut self, data: impl Any + Send + Sync) -> Terrain {
+        self.rule_data = Some(Arc::new(data));
+        self
+    }
+
+    /// What the rules of `layer` get to see at `pos`, given the modules in `map`
+    pub(crate) fn rule_context(
+        &self,
+        layer: usize,
+        pos: UVec2,
+        map: &HashMap<UVec2, TerrainModule>,
+    ) -> RuleContext {
+        RuleContext {
+            pos,
+            dimensions: self.dimensions,
+            layer: &self.layers[layer].name,
+            adjacents: self.adjacents(layer, pos, map),
+            data: self.rule_data.as_deref(),
+        }
+    }
+}
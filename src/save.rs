@@ -0,0 +1,447 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DungeonLayout, GenerationState, PrefabPlacement, ScatteredObject, Terrain, TerrainLayer,
+    TerrainModule, TerrainModuleId, URect,
+};
+
+/// Everything needed to rebuild a terrain exactly as it was, without running any rules.
+///
+/// Cells store module names, so a save stays valid when generation rules change or modules are
+/// added, as long as the modules it references are still added to the restored terrain under the
+/// same names. Scattered objects are saved as they were too. Saves taken during generation also
+/// keep the random number generator and the layers' constraints, so the restored terrain carries
+/// on exactly where the saved one was.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TerrainSave {
+    seed: u64,
+    dimensions: UVec2,
+    module_dimensions: Vec2,
+    layers: Vec<SavedLayer>,
+    /// Index of the layer that was being generated
+    layer: usize,
+    state: GenerationState,
+    /// How far the random number generator had got
+    #[serde(default)]
+    rng_position: u128,
+    #[serde(default)]
+    stalemates: u32,
+    #[serde(default)]
+    scattered: Option<Vec<ScatteredObject>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SavedLayer {
+    name: String,
+    /// Module per cell, row by row
+    cells: Vec<Option<SavedModule>>,
+    /// Biome per cell row by row, empty for layers without biomes
+    #[serde(default)]
+    biomes: Vec<Option<String>>,
+    #[serde(default)]
+    dungeon: Option<DungeonLayout>,
+    #[serde(default)]
+    required_passable: Vec<UVec2>,
+    #[serde(default)]
+    connect_attempts: u32,
+    #[serde(default)]
+    limit_attempts: u32,
+    #[serde(default)]
+    path_attempts: u32,
+    #[serde(default)]
+    path_cells: Vec<(UVec2, Vec<TerrainModuleId>)>,
+    #[serde(default)]
+    placements: Vec<PrefabPlacement>,
+}
+
+/// Why a save can't be restored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveError {
+    /// A layer has a different number of cells than the saved dimensions
+    CellCount {
+        layer: String,
+        expected: usize,
+        found: usize,
+    },
+    /// The saved state refers to a cell outside the saved dimensions
+    OutOfBounds(UVec2),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::CellCount {
+                layer,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Saved layer {} has {} cells, expected {}",
+                layer, found, expected
+            ),
+            SaveError::OutOfBounds(pos) => write!(f, "Saved cell {} is outside the terrain", pos),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+/// How a saved cell refers to its module
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SavedModule {
+    Name(String),
+    /// For modules without a name, ids change when modules are added before them
+    Id(TerrainModuleId),
+}
+
+impl SavedModule {
+    fn new(module: &TerrainModule) -> Self {
+        if module.name.is_empty() {
+            SavedModule::Id(module.id)
+        } else {
+            SavedModule::Name(module.name.clone())
+        }
+    }
+
+    fn resolve<'a>(&self, layer: &'a TerrainLayer) -> Option<&'a TerrainModule> {
+        match self {
+            SavedModule::Name(name) => layer.module_named(name),
+            SavedModule::Id(id) => layer.module(*id),
+        }
+    }
+}
+
+impl fmt::Display for SavedModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SavedModule::Name(name) => name.fmt(f),
+            SavedModule::Id(id) => id.fmt(f),
+        }
+    }
+}
+
+impl TerrainSave {
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn dimensions(&self) -> UVec2 {
+        self.dimensions
+    }
+
+    pub fn module_at(&self, layer: &str, pos: UVec2) -> Option<&SavedModule> {
+        if pos.x >= self.dimensions.x || pos.y >= self.dimensions.y {
+            return None;
+        }
+        let saved = self.layers.iter().find(|saved| saved.name == layer)?;
+        saved
+            .cells
+            .get((pos.y * self.dimensions.x + pos.x) as usize)?
+            .as_ref()
+    }
+
+    /// Checks that every layer covers the saved dimensions and every saved cell is inside them
+    pub fn validate(&self) -> Result<(), SaveError> {
+        let expected = (self.dimensions.x * self.dimensions.y) as usize;
+        let in_bounds = |pos: &UVec2| pos.x < self.dimensions.x && pos.y < self.dimensions.y;
+        for saved in self.layers.iter() {
+            let count_error = |found: usize| SaveError::CellCount {
+                layer: saved.name.clone(),
+                expected,
+                found,
+            };
+            if saved.cells.len() != expected {
+                return Err(count_error(saved.cells.len()));
+            }
+            if !saved.biomes.is_empty() && saved.biomes.len() != expected {
+                return Err(count_error(saved.biomes.len()));
+            }
+            let mut cells = saved
+                .required_passable
+                .iter()
+                .chain(saved.path_cells.iter().map(|(pos, _)| pos));
+            if let Some(pos) = cells.find(|pos| !in_bounds(pos)) {
+                return Err(SaveError::OutOfBounds(*pos));
+            }
+        }
+        if let GenerationState::PlacedModules(placed) = &self.state {
+            if let Some(pos) = placed.iter().find(|pos| !in_bounds(pos)) {
+                return Err(SaveError::OutOfBounds(*pos));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Terrain {
+    pub fn save(&self) -> TerrainSave {
+        let (layers, layer, state) = match &self.state {
+            // A terrain that is still being restored hasn't got anything in its layers yet
+            GenerationState::Restoring {
+                layers,
+                layer,
+                resume,
+            } => (layers.clone(), *layer, *resume.clone()),
+            state => {
+                let cells = URect::new(UVec2::ZERO, self.dimensions);
+                let layers = self
+                    .layers
+                    .iter()
+                    .map(|layer| SavedLayer {
+                        name: layer.name.clone(),
+                        cells: cells
+                            .positions()
+                            .map(|pos| layer.map.get(&pos).map(SavedModule::new))
+                            .collect(),
+                        biomes: if layer.biomes.is_empty() {
+                            vec![]
+                        } else {
+                            cells
+                                .positions()
+                                .map(|pos| layer.biomes.get(&pos).cloned())
+                                .collect()
+                        },
+                        dungeon: layer.dungeon.clone(),
+                        // Grid order, so the same terrain always saves the same way
+                        required_passable: cells
+                            .positions()
+                            .filter(|pos| layer.required_passable.contains(pos))
+                            .collect(),
+                        connect_attempts: layer.connect_attempts,
+                        limit_attempts: layer.limit_attempts,
+                        path_attempts: layer.path_attempts,
+                        path_cells: cells
+                            .positions()
+                            .filter_map(|pos| Some((pos, layer.path_cells.get(&pos)?.clone())))
+                            .collect(),
+                        placements: layer.placements.clone(),
+                    })
+                    .collect();
+                let state = match state {
+                    GenerationState::Finished => GenerationState::Done,
+                    state => state.clone(),
+                };
+                (layers, self.layer, state)
+            }
+        };
+        TerrainSave {
+            seed: self.seed,
+            dimensions: self.dimensions,
+            module_dimensions: self.module_dimensions,
+            layers,
+            layer,
+            state,
+            rng_position: self.rng.get_word_pos(),
+            stalemates: self.stalemates,
+            scattered: self.scattered.clone(),
+        }
+    }
+
+    /// Spawns the saved map instead of generating a new one, fails if the save doesn't hold
+    /// together.
+    ///
+    /// Set the terrain up with the same layers and modules as the original one, the saved ids are
+    /// resolved against them once the terrain is spawned.
+    pub fn with_save(mut self, save: TerrainSave) -> Result<Terrain, SaveError> {
+        save.validate()?;
+        self.dimensions = save.dimensions;
+        self.module_dimensions = save.module_dimensions;
+        self.seed = save.seed;
+        self.rng = ChaCha12Rng::seed_from_u64(save.seed);
+        self.rng.set_word_pos(save.rng_position);
+        self.stalemates = save.stalemates;
+        self.scattered = save.scattered;
+        self.state = GenerationState::Restoring {
+            layers: save.layers,
+            layer: save.layer,
+            resume: Box::new(save.state),
+        };
+        Ok(self)
+    }
+
+    pub(crate) fn restore(
+        &mut self,
+        layers: &[SavedLayer],
+        layer: usize,
+        resume: &GenerationState,
+    ) {
+        for saved in layers.iter() {
+            let layer = match self
+                .layers
+                .iter()
+                .position(|layer| layer.name == saved.name)
+            {
+                Some(layer) => layer,
+                None => {
+                    warn!("Saved terrain has unknown layer {}", saved.name);
+                    continue;
+                }
+            };
+            let cells: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+                .positions()
+                .collect();
+            for (pos, saved_module) in cells.iter().zip(saved.cells.iter()) {
+                let (pos, saved_module) = match saved_module {
+                    Some(saved_module) => (*pos, saved_module),
+                    None => continue,
+                };
+                // Already covered by a multi-cell module from an earlier cell
+                if self.layers[layer].map.contains_key(&pos) {
+                    continue;
+                }
+                match saved_module.resolve(&self.layers[layer]).cloned() {
+                    Some(module) => {
+                        if !self.insert(layer, pos, module) {
+                            warn!(
+                                "Saved module {} doesn't fit at {} on layer {}",
+                                saved_module, pos, saved.name
+                            );
+                        }
+                    }
+                    None => warn!(
+                        "Saved terrain references unknown module {} on layer {}",
+                        saved_module, saved.name
+                    ),
+                }
+            }
+            let terrain_layer = &mut self.layers[layer];
+            terrain_layer.biomes = cells
+                .iter()
+                .zip(saved.biomes.iter())
+                .filter_map(|(pos, biome)| Some((*pos, biome.clone()?)))
+                .collect();
+            terrain_layer.dungeon = saved.dungeon.clone();
+            terrain_layer.required_passable = saved.required_passable.iter().copied().collect();
+            terrain_layer.connect_attempts = saved.connect_attempts;
+            terrain_layer.limit_attempts = saved.limit_attempts;
+            terrain_layer.path_attempts = saved.path_attempts;
+            terrain_layer.path_cells = saved.path_cells.iter().cloned().collect();
+            terrain_layer.placements = saved.placements.clone();
+        }
+        self.layer = layer.min(self.layers.len() - 1);
+        self.state = match resume {
+            GenerationState::Done => {
+                // Saves from before scattered objects were saved
+                if self.scattered.is_none() {
+                    self.scatter_objects();
+                }
+                GenerationState::Finished
+            }
+            state => state.clone(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, finish, name_at},
+        GenerationType, Scatter,
+    };
+
+    fn terrain(seed: u64) -> Terrain {
+        testing::terrain(
+            GenerationType::WaveCollapse,
+            UVec2::new(6, 5),
+            &["grass", "sand", "water"],
+        )
+        .with_seed(seed)
+    }
+
+    /// `terrain` with `save` restored into it
+    fn restored(terrain: Terrain, save: TerrainSave) -> Terrain {
+        let mut terrain = terrain.with_save(save).unwrap();
+        // The first step rebuilds the saved layers
+        terrain.step();
+        terrain
+    }
+
+    #[test]
+    fn round_trip_keeps_every_cell() {
+        let mut original = terrain(3);
+        finish(&mut original);
+        let save = original.save();
+        let restored = restored(terrain(0), save.clone());
+        assert!(matches!(restored.state, GenerationState::Finished));
+        assert_eq!(restored.seed(), 3);
+        for pos in URect::new(UVec2::ZERO, original.dimensions).positions() {
+            let id = original.layers[0].module_at(pos).map(|module| module.id);
+            assert!(id.is_some());
+            assert_eq!(
+                restored.layers[0].module_at(pos).map(|module| module.id),
+                id
+            );
+            let name = name_at(&original, pos).unwrap().to_string();
+            assert_eq!(
+                save.module_at("ground", pos),
+                Some(&SavedModule::Name(name))
+            );
+        }
+        assert_eq!(format!("{:?}", restored.save()), format!("{:?}", save));
+    }
+
+    #[test]
+    fn restored_generation_carries_on_the_same_way() {
+        let mut original = terrain(5);
+        for _ in 0..8 {
+            original.step();
+        }
+        let mut restored = restored(terrain(0), original.save());
+        finish(&mut original);
+        finish(&mut restored);
+        assert_eq!(
+            format!("{:?}", restored.save()),
+            format!("{:?}", original.save())
+        );
+    }
+
+    #[test]
+    fn modules_are_restored_by_name() {
+        let mut original = terrain(2);
+        finish(&mut original);
+        // A module added in front shifts the ids of all the others
+        let shifted = testing::terrain(
+            GenerationType::WaveCollapse,
+            UVec2::new(6, 5),
+            &["mud", "grass", "sand", "water"],
+        );
+        let restored = restored(shifted, original.save());
+        for pos in URect::new(UVec2::ZERO, original.dimensions).positions() {
+            assert_eq!(name_at(&restored, pos), name_at(&original, pos));
+        }
+    }
+
+    #[test]
+    fn scattered_objects_are_restored_as_saved() {
+        let mut original = terrain(6).with_scatter(Scatter::new("tree", 4));
+        finish(&mut original);
+        assert!(!original.scattered().is_empty());
+        // Different scatters when loading don't change what was saved
+        let changed = terrain(0).with_scatter(Scatter::new("rock", 4).with_spacing(2.0));
+        let restored = restored(changed, original.save());
+        assert_eq!(restored.scattered(), original.scattered());
+    }
+
+    #[test]
+    fn rejects_saves_with_missing_cells() {
+        let mut original = terrain(1);
+        finish(&mut original);
+        let mut save = original.save();
+        save.layers[0].cells.pop();
+        assert_eq!(
+            terrain(0).with_save(save).err(),
+            Some(SaveError::CellCount {
+                layer: "ground".to_string(),
+                expected: 30,
+                found: 29,
+            })
+        );
+    }
+}
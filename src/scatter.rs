@@ -0,0 +1,281 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_inspector_egui::Inspectable;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    heightmap::{build, sample},
+    NoiseSource, Terrain, URect,
+};
+
+/// How many random spots are tried for each object
+const PLACEMENT_TRIES: usize = 30;
+
+/// Mixed into the terrain's seed, so scattering doesn't draw the same numbers as generation
+const SCATTER_SEED: u64 = 0x5ca7_7e12;
+
+/// Objects like trees, enemies or loot spread over the terrain once every layer is generated.
+///
+/// Spots are picked at random and kept if they are far enough from every object scattered so
+/// far, which spreads the objects out evenly like Poisson-disk sampling does. The results are
+/// found in [`Terrain::scattered`], to be spawned however the game likes.
+///
+/// Objects are scattered once the whole terrain is generated, from the seed alone, so the same
+/// seed and map always give the same objects. Edits and regenerated regions drop the objects on
+/// the cells they changed that don't fit there anymore, and scatter as many over those cells again.
+#[derive(Clone, Debug, Default, Inspectable)]
+pub struct Scatter {
+    /// Handed to every [`ScatteredObject`], to tell what to spawn
+    pub name: String,
+    /// Most objects placed, fewer are when there isn't room for them
+    pub count: usize,
+    /// Fewest cells between the objects and any other scattered object
+    pub spacing: f32,
+    /// Objects only go on cells with a module with any of these tags, on any layer. Empty allows
+    /// every cell.
+    pub tags: Vec<String>,
+    /// Objects only go on cells in any of these biomes, on any layer. Empty allows every cell.
+    pub biomes: Vec<String>,
+    pub density: Density,
+}
+
+impl Scatter {
+    pub fn new(name: impl Into<String>, count: usize) -> Self {
+        Self {
+            name: name.into(),
+            count,
+            ..default()
+        }
+    }
+
+    pub fn with_spacing(mut self, spacing: f32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn on_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn in_biome(mut self, biome: impl Into<String>) -> Self {
+        self.biomes.push(biome.into());
+        self
+    }
+
+    pub fn with_density(mut self, density: Density) -> Self {
+        self.density = density;
+        self
+    }
+}
+
+/// How likely a spot is to be kept
+#[derive(Clone, Debug, Inspectable)]
+pub enum Density {
+    /// Every spot that fits is kept
+    Uniform,
+    /// Spots are kept with the chance of the noise value at their cell, so objects cluster where
+    /// the noise is high
+    Noise { source: NoiseSource },
+}
+
+impl Default for Density {
+    fn default() -> Self {
+        Density::Uniform
+    }
+}
+
+/// Where a [`Scatter`] placed one of its objects
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScatteredObject {
+    /// Index into the terrain's scatters, in the order they were added
+    pub scatter: usize,
+    pub name: String,
+    pub cell: UVec2,
+    /// Position in cells, within `cell`
+    pub point: Vec2,
+    /// Position relative to the terrain, in the same space as the tiles
+    pub translation: Vec2,
+}
+
+impl Terrain {
+    /// Spreads objects over the terrain once it is generated
+    pub fn with_scatter(mut self, scatter: Scatter) -> Terrain {
+        self.scatters.push(scatter);
+        self
+    }
+
+    /// Objects placed by the terrain's scatters, once it is generated
+    pub fn scattered(&self) -> &[ScatteredObject] {
+        self.scattered.as_deref().unwrap_or(&[])
+    }
+
+    /// Places the objects of every scatter
+    pub(crate) fn scatter_objects(&mut self) {
+        let mut rng = ChaCha12Rng::seed_from_u64(self.seed ^ SCATTER_SEED);
+        let cells: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .collect();
+        self.scattered = Some(vec![]);
+        for (index, scatter) in self.scatters.clone().iter().enumerate() {
+            let placed = self.scatter_over(index, scatter.count, &cells, &mut rng);
+            if placed < scatter.count {
+                warn!(
+                    "Only scattered {} of {} {} objects",
+                    placed, scatter.count, scatter.name
+                );
+            }
+        }
+    }
+
+    /// Drops the objects on `changed` cells that don't fit them anymore, and scatters as many
+    /// objects as were dropped over those cells again
+    pub(crate) fn rescatter(&mut self, changed: &[UVec2]) {
+        let scattered = match self.scattered.take() {
+            Some(scattered) => scattered,
+            None => return,
+        };
+        let changed: HashSet<UVec2> = changed.iter().copied().collect();
+        let (kept, dropped): (Vec<ScatteredObject>, Vec<ScatteredObject>) =
+            scattered.into_iter().partition(|object| {
+                !changed.contains(&object.cell)
+                    || self
+                        .scatters
+                        .get(object.scatter)
+                        .map_or(false, |scatter| self.scatter_fits(scatter, object.cell))
+            });
+        self.scattered = Some(kept);
+        if dropped.is_empty() {
+            return;
+        }
+        // Grid order, so the same edit always scatters the same way
+        let cells: Vec<UVec2> = URect::new(UVec2::ZERO, self.dimensions)
+            .positions()
+            .filter(|pos| changed.contains(pos))
+            .collect();
+        let mut rng = ChaCha12Rng::seed_from_u64(self.rng.gen::<u64>() ^ SCATTER_SEED);
+        for index in 0..self.scatters.len() {
+            let count = dropped
+                .iter()
+                .filter(|object| object.scatter == index)
+                .count();
+            self.scatter_over(index, count, &cells, &mut rng);
+        }
+    }
+
+    /// Places up to `count` objects of the scatter at `index` on `cells`, returns how many it
+    /// placed
+    fn scatter_over(
+        &mut self,
+        index: usize,
+        count: usize,
+        cells: &[UVec2],
+        rng: &mut ChaCha12Rng,
+    ) -> usize {
+        if cells.is_empty() {
+            return 0;
+        }
+        let scatter = &self.scatters[index];
+        let noise = match &scatter.density {
+            Density::Uniform => None,
+            Density::Noise { source } => {
+                let sources = [*source];
+                let noise = build(&sources, self.seed);
+                Some((sources, noise))
+            }
+        };
+        let mut scattered = self.scattered.take().unwrap_or_default();
+        let mut placed = 0;
+        for _ in 0..count * PLACEMENT_TRIES {
+            if placed == count {
+                break;
+            }
+            let cell = cells[rng.gen_range(0..cells.len())];
+            let point =
+                cell.as_vec2() + Vec2::new(rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+            if !self.scatter_fits(scatter, cell) {
+                continue;
+            }
+            if let Some((sources, noise)) = &noise {
+                if rng.gen::<f64>() >= sample(sources, noise, cell) {
+                    continue;
+                }
+            }
+            let crowded = scattered.iter().any(|other| {
+                let spacing = scatter.spacing.max(self.scatters[other.scatter].spacing);
+                other.point.distance(point) < spacing
+            });
+            if crowded {
+                continue;
+            }
+            // Tiles are centered on their cell, so the cell starts half a tile before them
+            let offset = point - Vec2::splat(0.5);
+            scattered.push(ScatteredObject {
+                scatter: index,
+                name: scatter.name.clone(),
+                cell,
+                point,
+                translation: Vec2::new(offset.x, -offset.y) * self.module_dimensions,
+            });
+            placed += 1;
+        }
+        self.scattered = Some(scattered);
+        placed
+    }
+
+    /// Whether the tags and biomes at `cell` allow objects of `scatter`
+    fn scatter_fits(&self, scatter: &Scatter, cell: UVec2) -> bool {
+        let tags: Vec<&str> = scatter.tags.iter().map(|tag| tag.as_str()).collect();
+        let tagged = tags.is_empty()
+            || self
+                .layers
+                .iter()
+                .filter_map(|layer| layer.module_at(cell))
+                .any(|module| module.has_any_tag(&tags));
+        let in_biome = scatter.biomes.is_empty()
+            || self
+                .layers
+                .iter()
+                .filter_map(|layer| layer.biome_at(cell))
+                .any(|biome| scatter.biomes.iter().any(|allowed| allowed == biome));
+        tagged && in_biome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{self, finish, named},
+        CellEdit, GenerationType, TerrainModule,
+    };
+
+    #[test]
+    fn objects_on_changed_cells_are_rescattered() {
+        let mut terrain = testing::terrain(GenerationType::WaveCollapse, UVec2::new(8, 8), &[])
+            .with_module(TerrainModule {
+                tags: vec!["ground".to_string()],
+                ..named("grass")
+            })
+            .with_module(named("water"))
+            .with_scatter(Scatter::new("tree", 10).on_tag("ground"))
+            .with_seed(3);
+        finish(&mut terrain);
+        let water = testing::id(&terrain, "water");
+        let changed: Vec<UVec2> = terrain
+            .scattered()
+            .iter()
+            .map(|object| object.cell)
+            .collect();
+        let layer = terrain.layers[0].name.clone();
+        for cell in changed.iter() {
+            terrain.set_cell(&layer, *cell, water, CellEdit::Force);
+        }
+        terrain.rescatter(&changed);
+        assert!(terrain
+            .scattered()
+            .iter()
+            .all(|object| testing::name_at(&terrain, object.cell) == Some("grass")));
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use bevy::{ecs::system::EntityCommands, prelude::*};
+
+use crate::{Terrain, TerrainModule};
+
+/// Adds components to the tiles spawned for a module, see [`TerrainModule::spawner`].
+///
+/// Runs once for every placed module with the tile's entity, which already has its transform
+/// and, if the module has an image, its sprite. Wraps any closure, like
+/// [`GenerationRule`](crate::GenerationRule).
+#[derive(Clone, Default)]
+pub struct ModuleSpawner(Option<Arc<dyn Fn(&mut EntityCommands, &SpawnContext) + Send + Sync>>);
+
+impl ModuleSpawner {
+    pub fn new(
+        spawner: impl Fn(&mut EntityCommands, &SpawnContext) + Send + Sync + 'static,
+    ) -> Self {
+        Self(Some(Arc::new(spawner)))
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn spawn(&self, tile: &mut EntityCommands, context: &SpawnContext) {
+        if let Some(spawner) = &self.0 {
+            spawner(tile, context);
+        }
+    }
+}
+
+/// Where the tile a [`ModuleSpawner`] runs for was placed
+pub struct SpawnContext<'a> {
+    pub pos: UVec2,
+    /// Name of the layer the module is placed on
+    pub layer: &'a str,
+    pub module: &'a TerrainModule,
+    /// The terrain the tile is a child of
+    pub terrain: Entity,
+}
+
+impl TerrainModule {
+    /// Whether anything is spawned for the module, modules without an image, scene or spawner
+    /// only take up their cells
+    pub fn spawns(&self) -> bool {
+        self.image != Handle::default() || self.scene != Handle::default() || self.spawner.is_some()
+    }
+}
+
+impl Terrain {
+    /// Spawns a tile for every placed module that has none yet, as a child of `entity`
+    pub(crate) fn spawn_tiles(
+        &mut self,
+        commands: &mut Commands,
+        entity: Entity,
+        scenes: &mut Option<ResMut<SceneSpawner>>,
+    ) {
+        for layer in 0..self.layers.len() {
+            let terrain_layer = &self.layers[layer];
+            let mut spawned = vec![];
+            for (pos, module) in terrain_layer.map.iter() {
+                if terrain_layer.tiles.contains_key(pos)
+                    || terrain_layer.anchor(*pos) != *pos
+                    || !module.spawns()
+                {
+                    continue;
+                }
+                let mut transform = self.tile_transform(layer, *pos);
+                transform.translation += self.footprint_offset(module);
+                commands.entity(entity).with_children(|parent| {
+                    let mut tile = if module.image == Handle::default() {
+                        let mut tile = parent.spawn();
+                        tile.insert_bundle((transform, GlobalTransform::default()));
+                        tile
+                    } else {
+                        parent.spawn_bundle(SpriteBundle {
+                            transform,
+                            texture: module.image.clone(),
+                            ..default()
+                        })
+                    };
+                    let context = SpawnContext {
+                        pos: *pos,
+                        layer: &terrain_layer.name,
+                        module,
+                        terrain: entity,
+                    };
+                    module.spawner.spawn(&mut tile, &context);
+                    spawned.push((*pos, tile.id()));
+                });
+            }
+            for (pos, tile) in spawned.iter() {
+                let scene = &self.layers[layer].map[pos].scene;
+                if *scene == Handle::default() {
+                    continue;
+                }
+                match scenes {
+                    Some(scenes) => scenes.spawn_as_child(scene.clone(), *tile),
+                    None => warn!("Can't spawn scenes without the scene plugin"),
+                }
+            }
+            self.layers[layer].tiles.extend(spawned);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+
+use crate::{Adjacents, RuleContext, Side, TerrainModule};
+
+/// Tags each side of a module shows to its neighbours.
+///
+/// Two modules can only be placed next to each other when the sides facing each other share a
+/// tag. A side without tags fits anything, so modules only need sockets where they matter.
+#[derive(Clone, Debug, Default, Inspectable, Reflect)]
+pub struct Sockets {
+    pub n: Vec<String>,
+    pub e: Vec<String>,
+    pub s: Vec<String>,
+    pub w: Vec<String>,
+}
+
+impl Sockets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The same tag on every side
+    pub fn all(tag: impl Into<String>) -> Self {
+        let tag = tag.into();
+        Self {
+            n: vec![tag.clone()],
+            e: vec![tag.clone()],
+            s: vec![tag.clone()],
+            w: vec![tag],
+        }
+    }
+
+    pub fn with(mut self, side: Side, tag: impl Into<String>) -> Self {
+        let tags = match side {
+            Side::North => &mut self.n,
+            Side::East => &mut self.e,
+            Side::South => &mut self.s,
+            Side::West => &mut self.w,
+        };
+        tags.push(tag.into());
+        self
+    }
+
+    pub fn side(&self, side: Side) -> &[String] {
+        match side {
+            Side::North => &self.n,
+            Side::East => &self.e,
+            Side::South => &self.s,
+            Side::West => &self.w,
+        }
+    }
+}
+
+impl TerrainModule {
+    /// Whether the module's rule and sockets allow it in `context`
+    pub fn fits(&self, context: &RuleContext) -> bool {
+        self.sockets_fit(&context.adjacents) && self.generation_rule.allows(context)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|own| own == tag)
+    }
+
+    pub fn has_any_tag(&self, tags: &[&str]) -> bool {
+        tags.iter().any(|tag| self.has_tag(tag))
+    }
+
+    /// Whether the sockets of this module match those of the neighbours in `adjacents`
+    pub fn sockets_fit(&self, adjacents: &Adjacents) -> bool {
+        Side::ALL.iter().all(|side| {
+            let own = self.sockets.side(*side);
+            let other = match adjacents.side(*side) {
+                Some(neighbour) => neighbour.sockets.side(side.opposite()),
+                None => return true,
+            };
+            own.is_empty() || other.is_empty() || own.iter().any(|tag| other.contains(tag))
+        })
+    }
+}
+
+impl Adjacents {
+    pub fn side(&self, side: Side) -> Option<&TerrainModule> {
+        match side {
+            Side::North => self.n.as_ref(),
+            Side::East => self.e.as_ref(),
+            Side::South => self.s.as_ref(),
+            Side::West => self.w.as_ref(),
+        }
+    }
+
+    /// Whether every placed neighbour has `tag`
+    pub fn all_have_tag(&self, tag: &str) -> bool {
+        self.list()
+            .into_iter()
+            .flatten()
+            .all(|module| module.has_tag(tag))
+    }
+
+    pub fn any_has_tag(&self, tag: &str) -> bool {
+        self.list()
+            .into_iter()
+            .flatten()
+            .any(|module| module.has_tag(tag))
+    }
+
+    /// Whether the neighbour on `side` has any of `tags`, false if nothing is placed there
+    pub fn side_has_any_tag(&self, side: Side, tags: &[&str]) -> bool {
+        self.side(side)
+            .map_or(false, |module| module.has_any_tag(tags))
+    }
+}
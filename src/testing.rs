@@ -0,0 +1,44 @@
+//! Terrains for the unit tests of the other modules
+
+use bevy::prelude::*;
+
+use crate::{GenerationState, GenerationType, Terrain, TerrainModule, TerrainModuleId};
+
+/// Module without rules, sockets or an image
+pub(crate) fn named(name: &str) -> TerrainModule {
+    TerrainModule {
+        name: name.to_string(),
+        ..default()
+    }
+}
+
+/// Terrain with a single layer holding a [`named`] module for each of `names`
+pub(crate) fn terrain(gen_type: GenerationType, dimensions: UVec2, names: &[&str]) -> Terrain {
+    names.iter().fold(
+        Terrain::new(gen_type, dimensions, Vec2::splat(16.0)),
+        |terrain, name| terrain.with_module(named(name)),
+    )
+}
+
+/// Id of the module called `name` on the first layer
+pub(crate) fn id(terrain: &Terrain, name: &str) -> TerrainModuleId {
+    terrain.layers[0].id_of(name).unwrap()
+}
+
+/// Name of the module at `pos` on the first layer
+pub(crate) fn name_at(terrain: &Terrain, pos: UVec2) -> Option<&str> {
+    terrain.layers[0]
+        .module_at(pos)
+        .map(|module| module.name.as_str())
+}
+
+/// Steps until generation finishes, as the generation system would
+pub(crate) fn finish(terrain: &mut Terrain) {
+    for _ in 0..10000 {
+        if matches!(terrain.state, GenerationState::Finished) {
+            return;
+        }
+        terrain.step();
+    }
+    panic!("Generation didn't finish");
+}